@@ -1,3 +1,6 @@
+# Runs every test with the Python interpreter.
+COMMAND: python3
+
 TEST hello world.py:
 ---
 print("Hello, World!")
@@ -0,0 +1,160 @@
+use std::{
+    fmt::Write as _,
+    io::{BufRead, Write},
+    ops::Range,
+};
+
+// Replacement of a block's content, given as a byte range of the .plt file.
+pub struct Edit {
+    pub span: Range<usize>,
+    pub text: String,
+}
+
+// Applies the edits to `source`, leaving everything outside of them
+// byte-for-byte as it was.
+pub fn apply(source: &str, mut edits: Vec<Edit>) -> String {
+    edits.sort_by_key(|edit| edit.span.start);
+
+    let mut result = String::with_capacity(source.len());
+    let mut copied_up_to = 0;
+    for edit in edits {
+        result.push_str(&source[copied_up_to..edit.span.start]);
+        result.push_str(&edit.text);
+        copied_up_to = edit.span.end;
+    }
+    result.push_str(&source[copied_up_to..]);
+    result
+}
+
+// Checks that `text` reads back unchanged when written into a block closed by
+// `separator`, and returns why it wouldn't otherwise.
+pub fn check_block_text(text: &str, separator: &str) -> Result<(), String> {
+    if text
+        .lines()
+        .any(|line| line.trim_end_matches([' ', '\t', '\r']) == separator)
+    {
+        return Err(format!(
+            "it has a line with just the separator '{separator}', use a '<<<NAME' block instead"
+        ));
+    }
+    if !text.is_empty() && !text.ends_with('\n') {
+        return Err(
+            "it doesn't end with a newline, add 'FINAL_NEWLINE: strip' to the test".to_string(),
+        );
+    }
+    Ok(())
+}
+
+// Formats bytes for an 'EXPECTED: hex' block, 16 bytes per line.
+pub fn to_hex_block(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for row in bytes.chunks(16) {
+        let row: Vec<String> = row.iter().map(|b| format!("{b:02x}")).collect();
+        let _ = writeln!(text, "{}", row.join(" "));
+    }
+    text
+}
+
+// Formats bytes for an 'EXPECTED: escaped' block, breaking the line after
+// every '\n' escape. Printable ASCII is kept as is.
+pub fn to_escaped_block(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for &byte in bytes {
+        match byte {
+            b'\n' => text.push_str("\\n\n"),
+            b'\r' => text.push_str("\\r"),
+            b'\t' => text.push_str("\\t"),
+            0 => text.push_str("\\0"),
+            b'\\' => text.push_str("\\\\"),
+            b' '..=b'~' => text.push(byte as char),
+            _ => {
+                let _ = write!(text, "\\x{byte:02x}");
+            }
+        }
+    }
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text
+}
+
+// Asks on the terminal whether to update the test. Anything but "y" or
+// "yes", including the end of stdin, rejects.
+pub fn confirm(name: &str, line: usize) -> bool {
+    print!("Update the expected output of '{name}' on line {line}? [y/N] ");
+    let _ = std::io::stdout().flush();
+
+    let mut answer = String::new();
+    if std::io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, check_block_text, to_escaped_block, to_hex_block, Edit};
+
+    fn edit(span: std::ops::Range<usize>, text: &str) -> Edit {
+        Edit {
+            span,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn apply_keeps_the_rest_of_the_file() {
+        let source = "TEST a: \r\n---\nold\n---\n\n  # note\t\nTEST b:\n---\n---\nend";
+        let old = source.find("old").unwrap();
+        let empty = source.rfind("---\n---").unwrap() + "---\n".len();
+        // Edits may come in any order, and an empty block has an empty span.
+        let edits = vec![edit(empty..empty, "two\n"), edit(old..old + 4, "one\n1\n")];
+        assert_eq!(
+            apply(source, edits),
+            "TEST a: \r\n---\none\n1\n---\n\n  # note\t\nTEST b:\n---\ntwo\n---\nend"
+        );
+        assert_eq!(apply(source, Vec::new()), source);
+        assert_eq!(
+            apply(source, vec![edit(old..old + 4, "")]),
+            source.replace("old\n", "")
+        );
+    }
+
+    #[test]
+    fn block_text_has_to_read_back() {
+        assert!(check_block_text("", "---").is_ok());
+        assert!(check_block_text("a\n  ---\n----\n", "---").is_ok());
+        assert!(check_block_text("a\n---\nb\n", "---").is_err());
+        assert!(check_block_text("a\n--- \t\r\n", "---").is_err());
+        assert!(check_block_text("END\n", "END").is_err());
+        assert!(check_block_text("---\n", "END").is_ok());
+        assert!(check_block_text("a", "---").is_err());
+    }
+
+    #[test]
+    fn hex_block_round_trip() {
+        let bytes: Vec<u8> = (0..=255).chain([0x0a, 0xff]).collect();
+        let block = to_hex_block(&bytes);
+        assert_eq!(block.lines().count(), 17);
+        assert_eq!(
+            block.lines().next(),
+            Some("00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f")
+        );
+        assert_eq!(crate::parse_hex_bytes(&block).unwrap(), bytes);
+        assert_eq!(to_hex_block(&[]), "");
+    }
+
+    #[test]
+    fn escaped_block_round_trip() {
+        let bytes: Vec<u8> = (0..=255).chain(*b"a\\n\n\r\n").collect();
+        let block = to_escaped_block(&bytes);
+        assert!(block.ends_with('\n'));
+        assert!(check_block_text(&block, "---").is_ok());
+        assert_eq!(crate::parse_escaped_bytes(&block).unwrap(), bytes);
+        assert_eq!(to_escaped_block(b"a\nb"), "a\\n\nb\n");
+        assert_eq!(
+            crate::parse_escaped_bytes(&to_escaped_block(b"")).unwrap(),
+            b""
+        );
+    }
+}
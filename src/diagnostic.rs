@@ -0,0 +1,104 @@
+use std::fmt::Write as _;
+
+// An error in a .plt file, located by the byte offset it points at.
+pub struct Diagnostic {
+    pub offset: usize,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+// Renders the diagnostic like a compiler does, with the `file:line:col` of the
+// error, the offending source line and a caret under the column:
+//
+//     error: expected 'TEST' directive
+//       --> tests/print.plt:12:1
+//        |
+//     12 | TETS hello:
+//        | ^
+//        = hint: ...
+pub fn render(path: &str, source: &str, diagnostic: &Diagnostic) -> String {
+    let offset = floor_char_boundary(source, diagnostic.offset.min(source.len()));
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[offset..]
+        .find('\n')
+        .map_or(source.len(), |i| offset + i);
+    let line_number = source[..offset].matches('\n').count() + 1;
+    let source_line = source[line_start..line_end].trim_end_matches('\r');
+    let before_caret = &source[line_start..offset];
+    let column = before_caret.chars().count() + 1;
+
+    // Tabs are kept in front of the caret, so it lines up with the source.
+    let caret_indent: String = before_caret
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let gutter = " ".repeat(line_number.to_string().len());
+
+    let mut out = String::new();
+    let _ = writeln!(out, "error: {}", diagnostic.message);
+    let _ = writeln!(out, "{gutter}--> {path}:{line_number}:{column}");
+    let _ = writeln!(out, "{gutter} |");
+    let _ = writeln!(out, "{line_number} | {source_line}");
+    let _ = writeln!(out, "{gutter} | {caret_indent}^");
+    if let Some(hint) = &diagnostic.hint {
+        let _ = writeln!(out, "{gutter} = hint: {hint}");
+    }
+    out
+}
+
+// Line number (1-based) of the byte offset.
+pub fn line_of(source: &str, offset: usize) -> usize {
+    let offset = floor_char_boundary(source, offset.min(source.len()));
+    source[..offset].matches('\n').count() + 1
+}
+
+fn floor_char_boundary(source: &str, mut offset: usize) -> usize {
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{line_of, render, Diagnostic};
+
+    fn diagnostic(offset: usize, hint: Option<&str>) -> Diagnostic {
+        Diagnostic {
+            offset,
+            message: "bad".to_string(),
+            hint: hint.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn render_points_at_the_column() {
+        let source = "COMMAND: cat\r\n\tTIMEOUT: é 5\r\n";
+        let offset = source.find('5').unwrap();
+        let expected = "\
+error: bad
+ --> a.plt:2:13
+  |
+2 | \tTIMEOUT: é 5
+  | \t           ^
+  = hint: use 5s
+";
+        assert_eq!(
+            render("a.plt", source, &diagnostic(offset, Some("use 5s"))),
+            expected
+        );
+    }
+
+    #[test]
+    fn render_at_the_end_of_the_file() {
+        let source: String = (1..=10).map(|n| format!("{n}\n")).collect();
+        // The source line is empty, so it ends with the space after '|'.
+        let expected = "error: bad\n  --> a.plt:11:1\n   |\n11 | \n   | ^\n";
+        assert_eq!(
+            render("a.plt", &source, &diagnostic(source.len() + 5, None)),
+            expected
+        );
+        assert_eq!(line_of(&source, source.len()), 11);
+        assert_eq!(line_of("é\n", 1), 1);
+    }
+}
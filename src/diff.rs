@@ -0,0 +1,513 @@
+use std::fmt::Write as _;
+
+const CONTEXT_LINES: usize = 3;
+
+// Largest LCS table built for a diff, about 32 MB. Bigger changed regions are
+// shown as all of the expected lines removed and all of the new ones added.
+const MAX_LCS_CELLS: usize = 1 << 22;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Clone, Copy)]
+enum Op {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+// Renders a unified diff from `expected` to `got` with the line numbers of
+// both sides. A line replaced by exactly one other line also gets a '^' marker
+// under the first character that differs. With `escape`, whitespace and
+// control characters are rendered visibly, see `escape_line`.
+pub fn unified_diff(expected: &str, got: &str, color: bool, escape: bool) -> String {
+    let old: Vec<&str> = expected.split_inclusive('\n').collect();
+    let new: Vec<&str> = got.split_inclusive('\n').collect();
+    let ops = diff_lines(&old, &new);
+    let width = old.len().max(new.len()).max(1).to_string().len();
+    let render = |line: &str| {
+        if escape {
+            escape_line(line)
+        } else {
+            line.to_string()
+        }
+    };
+
+    let mut out = String::new();
+    for (start, end) in hunks(&ops) {
+        let header = hunk_header(&ops, start, end);
+        let _ = writeln!(out, "{}", paint(&header, CYAN, color));
+
+        let ops = &ops[start..end];
+        let mut k = 0;
+        while k < ops.len() {
+            if let Op::Equal(i, j) = ops[k] {
+                let line = format!(" {:>width$} {:>width$} | {}", i + 1, j + 1, render(old[i]));
+                write_line(&mut out, &line, "", color);
+                write_missing_newline(&mut out, old[i], !escape && i + 1 == old.len());
+                k += 1;
+                continue;
+            }
+
+            let mut deleted = Vec::new();
+            let mut inserted = Vec::new();
+            while let Some(Op::Delete(i)) = ops.get(k) {
+                deleted.push(*i);
+                k += 1;
+            }
+            while let Some(Op::Insert(j)) = ops.get(k) {
+                inserted.push(*j);
+                k += 1;
+            }
+
+            for &i in &deleted {
+                let line = format!("-{:>width$} {:width$} | {}", i + 1, "", render(old[i]));
+                write_line(&mut out, &line, RED, color);
+                write_missing_newline(&mut out, old[i], !escape && i + 1 == old.len());
+            }
+            for &j in &inserted {
+                let line = format!("+{:width$} {:>width$} | {}", "", j + 1, render(new[j]));
+                write_line(&mut out, &line, GREEN, color);
+                write_missing_newline(&mut out, new[j], !escape && j + 1 == new.len());
+            }
+            if let ([i], [j]) = (deleted.as_slice(), inserted.as_slice()) {
+                let prefix = " ".repeat(2 * width + 5);
+                let marker = first_difference_marker(old[*i], new[*j], escape);
+                let _ = writeln!(out, "{prefix}{}", paint(&marker, CYAN, color));
+            }
+        }
+    }
+
+    out
+}
+
+fn write_line(out: &mut String, line: &str, color_code: &str, color: bool) {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    if color_code.is_empty() {
+        let _ = writeln!(out, "{line}");
+    } else {
+        let _ = writeln!(out, "{}", paint(line, color_code, color));
+    }
+}
+
+fn write_missing_newline(out: &mut String, line: &str, is_last: bool) {
+    if is_last && !line.ends_with('\n') {
+        let _ = writeln!(out, "\\ No newline at end of output");
+    }
+}
+
+fn paint(text: &str, color_code: &str, color: bool) -> String {
+    if color {
+        format!("{color_code}{text}{RESET}")
+    } else {
+        text.to_string()
+    }
+}
+
+// Builds a line with a '^' under the first character where the two lines
+// differ. Tabs before it are kept, so the marker lines up with the text.
+fn first_difference_marker(old: &str, new: &str, escape: bool) -> String {
+    let common: String = old
+        .chars()
+        .zip(new.chars())
+        .take_while(|(a, b)| a == b)
+        .map(|(c, _)| c)
+        .collect();
+    let mut marker = if escape {
+        " ".repeat(escape_line(&common).chars().count())
+    } else {
+        common
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect()
+    };
+    marker.push('^');
+    marker
+}
+
+// Makes whitespace and control characters visible: '\t', '\r', '\n' and NUL
+// are written as escapes, other control characters as '\xNN' or '\u{NNNN}'
+// and trailing spaces as '\x20'. Backslashes are doubled to stay unambiguous.
+pub fn escape_line(line: &str) -> String {
+    let content = line.trim_end_matches(['\n', '\r']);
+    let trailing_spaces_start = content.trim_end_matches(' ').len();
+
+    let mut escaped = String::new();
+    for (offset, c) in line.char_indices() {
+        match c {
+            ' ' if trailing_spaces_start <= offset && offset < content.len() => {
+                escaped.push_str("\\x20")
+            }
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            '\0' => escaped.push_str("\\0"),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() && (c as u32) < 0x100 => {
+                let _ = write!(escaped, "\\x{:02x}", c as u32);
+            }
+            c if c.is_control() || is_invisible(c) => {
+                let _ = write!(escaped, "\\u{{{:04x}}}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200b}'..='\u{200f}' | '\u{2028}' | '\u{2029}' | '\u{feff}' | '\u{00a0}'
+    )
+}
+
+// True when the texts only differ in whitespace, e.g. a trailing space or
+// "\r\n" instead of "\n".
+pub fn differs_only_in_whitespace(a: &str, b: &str) -> bool {
+    let a = a.chars().filter(|c| !c.is_whitespace());
+    let b = b.chars().filter(|c| !c.is_whitespace());
+    a.eq(b)
+}
+
+// Byte offset, line and column (both 1-based, column counted in characters)
+// of the first difference between the texts.
+pub fn first_difference(a: &str, b: &str) -> Option<(usize, usize, usize)> {
+    let offset = a.bytes().zip(b.bytes()).take_while(|(x, y)| x == y).count();
+    if offset == a.len() && offset == b.len() {
+        return None;
+    }
+
+    // Step back to a char boundary, in case the difference is inside a char.
+    let mut char_offset = offset;
+    while !a.is_char_boundary(char_offset) {
+        char_offset -= 1;
+    }
+    let before = &a[..char_offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let column = before[line_start..].chars().count() + 1;
+    Some((offset, line, column))
+}
+
+fn hunk_header(ops: &[Op], start: usize, end: usize) -> String {
+    let is_old = |op: &Op| !matches!(op, Op::Insert(_));
+    let is_new = |op: &Op| !matches!(op, Op::Delete(_));
+    let old_before = ops[..start].iter().filter(|op| is_old(op)).count();
+    let new_before = ops[..start].iter().filter(|op| is_new(op)).count();
+    let old_len = ops[start..end].iter().filter(|op| is_old(op)).count();
+    let new_len = ops[start..end].iter().filter(|op| is_new(op)).count();
+
+    // An empty side starts at the line preceding the hunk, like in diff(1).
+    let old_start = if old_len == 0 {
+        old_before
+    } else {
+        old_before + 1
+    };
+    let new_start = if new_len == 0 {
+        new_before
+    } else {
+        new_before + 1
+    };
+    format!("@@ -{old_start},{old_len} +{new_start},{new_len} @@")
+}
+
+// Groups the changes into hunks of (start, end) indices into `ops`, with up
+// to CONTEXT_LINES unchanged lines around them. Close hunks are merged.
+fn hunks(ops: &[Op]) -> Vec<(usize, usize)> {
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (k, op) in ops.iter().enumerate() {
+        if let Op::Equal(..) = op {
+            continue;
+        }
+        let start = k.saturating_sub(CONTEXT_LINES);
+        let end = (k + 1 + CONTEXT_LINES).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    hunks
+}
+
+// Longest common subsequence of the lines. The common prefix and suffix are
+// stripped first, so the quadratic part only covers the region that changed.
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut ops: Vec<Op> = (0..prefix).map(|i| Op::Equal(i, i)).collect();
+    if MAX_LCS_CELLS < (a.len() + 1).saturating_mul(b.len() + 1) {
+        ops.extend((0..a.len()).map(|i| Op::Delete(prefix + i)));
+        ops.extend((0..b.len()).map(|j| Op::Insert(prefix + j)));
+    } else {
+        ops.extend(lcs_ops(a, b, prefix));
+    }
+    ops.extend((0..suffix).map(|k| Op::Equal(old.len() - suffix + k, new.len() - suffix + k)));
+
+    ops
+}
+
+fn lcs_ops(a: &[&str], b: &[&str], prefix: usize) -> Vec<Op> {
+    // lcs[i][j] is the length of the LCS of a[i..] and b[j..].
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ops.push(Op::Equal(prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(Op::Delete(prefix + i));
+            i += 1;
+        } else {
+            ops.push(Op::Insert(prefix + j));
+            j += 1;
+        }
+    }
+    ops
+}
+
+// Hex dump of the 16-byte rows around `offset`, with the offset of each row
+// and its printable ASCII characters.
+pub fn hex_dump_around(bytes: &[u8], offset: usize) -> String {
+    let start = offset.saturating_sub(16) / 16 * 16;
+    let end = (offset / 16 * 16 + 32).min(bytes.len());
+
+    let mut out = String::new();
+    for row_start in (start..end).step_by(16) {
+        let row = &bytes[row_start..(row_start + 16).min(end)];
+        let _ = write!(out, "{row_start:08x} ");
+        for k in 0..16 {
+            match row.get(k) {
+                Some(byte) => {
+                    let mark = if row_start + k == offset { '>' } else { ' ' };
+                    let _ = write!(out, "{mark}{byte:02x}");
+                }
+                None => out.push_str("   "),
+            }
+        }
+        let ascii: String = row
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = writeln!(out, "  |{ascii}|");
+    }
+    if bytes.len() <= offset {
+        let _ = writeln!(out, "{:08x}  <end of output>", bytes.len());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        diff_lines, differs_only_in_whitespace, escape_line, first_difference, hex_dump_around,
+        hunk_header, hunks, unified_diff, Op,
+    };
+
+    fn lines(text: &str) -> Vec<&str> {
+        text.split_inclusive('\n').collect()
+    }
+
+    fn headers(expected: &str, got: &str) -> Vec<String> {
+        let ops = diff_lines(&lines(expected), &lines(got));
+        hunks(&ops)
+            .into_iter()
+            .map(|(start, end)| hunk_header(&ops, start, end))
+            .collect()
+    }
+
+    fn numbered(from: usize, to: usize) -> String {
+        (from..to).map(|n| format!("{n}\n")).collect()
+    }
+
+    // Replaces the lines that are just `n` in the output of `numbered`.
+    fn replace_lines(text: &str, changes: &[(usize, &str)]) -> String {
+        let replace = |line: &str| {
+            let n: usize = line.trim_end().parse().unwrap();
+            match changes.iter().find(|(at, _)| *at == n) {
+                Some((_, with)) => format!("{with}\n"),
+                None => line.to_string(),
+            }
+        };
+        text.split_inclusive('\n').map(replace).collect()
+    }
+
+    #[test]
+    fn replaced_line() {
+        let diff = unified_diff("a\nb\nc\n", "a\nx\nc\n", false, false);
+        let expected = "\
+@@ -1,3 +1,3 @@
+ 1 1 | a
+-2   | b
++  2 | x
+       ^
+ 3 3 | c
+";
+        assert_eq!(diff, expected);
+        assert_eq!(unified_diff("a\n", "a\n", false, false), "");
+    }
+
+    #[test]
+    fn missing_newline_and_wide_numbers() {
+        let diff = unified_diff("a", "ab", false, false);
+        let expected = "\
+@@ -1,1 +1,1 @@
+-1   | a
+\\ No newline at end of output
++  1 | ab
+\\ No newline at end of output
+        ^
+";
+        assert_eq!(diff, expected);
+
+        let old = numbered(1, 11);
+        let new = replace_lines(&old, &[(10, "ten")]);
+        let diff = unified_diff(&old, &new, false, false);
+        assert!(diff.starts_with("@@ -7,4 +7,4 @@\n  7  7 | 7\n"));
+        assert!(diff.contains("\n-10    | 10\n+   10 | ten\n"));
+    }
+
+    #[test]
+    fn hunks_keep_context_and_merge() {
+        let old = numbered(1, 21);
+        // Changes with up to twice CONTEXT_LINES unchanged lines between them
+        // share a hunk.
+        let close = replace_lines(&old, &[(5, "five"), (12, "twelve")]);
+        assert_eq!(headers(&old, &close), ["@@ -2,14 +2,14 @@"]);
+        let far = replace_lines(&old, &[(5, "five"), (13, "thirteen")]);
+        assert_eq!(
+            headers(&old, &far),
+            ["@@ -2,7 +2,7 @@", "@@ -10,7 +10,7 @@"]
+        );
+        // Context is cut at both ends of the output.
+        let ends = replace_lines(&old, &[(1, "one"), (20, "twenty")]);
+        assert_eq!(
+            headers(&old, &ends),
+            ["@@ -1,4 +1,4 @@", "@@ -17,4 +17,4 @@"]
+        );
+    }
+
+    #[test]
+    fn hunk_header_of_empty_side() {
+        assert_eq!(headers("", "a\n"), ["@@ -0,0 +1,1 @@"]);
+        assert_eq!(headers("a\n", ""), ["@@ -1,1 +0,0 @@"]);
+        // An empty side starts at the line before the hunk.
+        let old = numbered(1, 11);
+        let inserted = replace_lines(&old, &[(5, "5\nnew")]);
+        let ops = diff_lines(&lines(&old), &lines(&inserted));
+        let start = ops
+            .iter()
+            .position(|op| matches!(op, Op::Insert(_)))
+            .unwrap();
+        assert_eq!(hunk_header(&ops, start, start + 1), "@@ -5,0 +6,1 @@");
+        assert_eq!(headers(&old, &inserted), ["@@ -3,6 +3,7 @@"]);
+    }
+
+    #[test]
+    fn large_changes_skip_the_lcs() {
+        let equal = |ops: &[Op]| ops.iter().filter(|op| matches!(op, Op::Equal(..))).count();
+        let old = format!("a\n{}same\n{}z\n", "x\n".repeat(999), "x\n".repeat(999));
+        let new = format!("a\n{}same\n{}z\n", "y\n".repeat(999), "y\n".repeat(999));
+        // The common middle line is found below the limit.
+        assert_eq!(equal(&diff_lines(&lines(&old), &lines(&new))), 3);
+
+        let old = format!("a\n{}same\n{}z\n", "x\n".repeat(1100), "x\n".repeat(1100));
+        let new = format!("a\n{}same\n{}z\n", "y\n".repeat(1100), "y\n".repeat(1100));
+        let (old, new) = (lines(&old), lines(&new));
+        let ops = diff_lines(&old, &new);
+        // Above it, only the common prefix and suffix are kept.
+        assert_eq!(equal(&ops), 2);
+        assert_eq!(ops.len(), 2 + 2 * 2201);
+        assert!(matches!(ops[1], Op::Delete(1)));
+        assert!(matches!(ops[2202], Op::Insert(1)));
+        assert!(matches!(ops[ops.len() - 1], Op::Equal(2202, 2202)));
+    }
+
+    #[test]
+    fn hex_dump_rows_around_offset() {
+        let bytes: Vec<u8> = (b'a'..=b'z').chain(0..14).collect();
+        let dump = hex_dump_around(&bytes, 20);
+        let expected = "\
+00000000  61 62 63 64 65 66 67 68 69 6a 6b 6c 6d 6e 6f 70  |abcdefghijklmnop|
+00000010  71 72 73 74>75 76 77 78 79 7a 00 01 02 03 04 05  |qrstuvwxyz......|
+00000020  06 07 08 09 0a 0b 0c 0d                          |........|
+";
+        assert_eq!(dump, expected);
+
+        // Only the row before and the row after the offset are shown.
+        let bytes = vec![b'.'; 100];
+        let dump = hex_dump_around(&bytes, 48);
+        let rows: Vec<&str> = dump.lines().map(|row| &row[..8]).collect();
+        assert_eq!(rows, ["00000020", "00000030", "00000040"]);
+
+        let dump = hex_dump_around(b"abc", 3);
+        let expected = "\
+00000000  61 62 63                                         |abc|
+00000003  <end of output>
+";
+        assert_eq!(dump, expected);
+    }
+
+    #[test]
+    fn escaped_lines() {
+        assert_eq!(escape_line("a b  \n"), "a b\\x20\\x20\\n");
+        assert_eq!(escape_line("a \r\n"), "a\\x20\\r\\n");
+        assert_eq!(escape_line("\ta\0b\\"), "\\ta\\0b\\\\");
+        assert_eq!(escape_line("\x1b[0m\x7f"), "\\x1b[0m\\x7f");
+        assert_eq!(escape_line("a\u{200b}b\u{feff}"), "a\\u{200b}b\\u{feff}");
+        assert_eq!(escape_line("é ü"), "é ü");
+    }
+
+    #[test]
+    fn whitespace_only_differences() {
+        assert!(differs_only_in_whitespace("a\n", "a \n"));
+        assert!(differs_only_in_whitespace("a\r\nb\r\n", "a\nb\n"));
+        assert!(differs_only_in_whitespace("a b", "a\tb\n"));
+        assert!(!differs_only_in_whitespace("a\0", "a"));
+        assert!(!differs_only_in_whitespace("ab", "a b c"));
+    }
+
+    #[test]
+    fn first_differences() {
+        assert_eq!(first_difference("abc", "abc"), None);
+        assert_eq!(first_difference("abc", "abd"), Some((2, 1, 3)));
+        assert_eq!(first_difference("a\nb\n", "a\nb"), Some((3, 2, 2)));
+        assert_eq!(first_difference("a\r\n", "a\n"), Some((1, 1, 2)));
+        assert_eq!(first_difference("a \n", "a\n"), Some((1, 1, 2)));
+        assert_eq!(first_difference("a\0b", "a\0c"), Some((2, 1, 3)));
+        assert_eq!(first_difference("", "a"), Some((0, 1, 1)));
+        // Columns count characters, and a difference inside of a character
+        // is reported at its start.
+        assert_eq!(first_difference("x\nyé!", "x\nyé?"), Some((5, 2, 3)));
+        assert_eq!(first_difference("aé", "aè"), Some((2, 1, 2)));
+        assert_eq!(first_difference("日本", "日木"), Some((5, 1, 2)));
+    }
+}
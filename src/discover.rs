@@ -0,0 +1,158 @@
+use std::path::Path;
+
+pub const DEFAULT_INCLUDE: &str = "*.plt";
+
+// Expands every command line path into the list of test files to run.
+// Explicitly named files are always kept, directories are walked recursively
+// and only files matching the include globs (and none of the exclude globs)
+// are collected. Files found in a directory are sorted so runs are stable.
+pub fn collect_test_files(paths: &[String], include: &[String], exclude: &[String]) -> Vec<String> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        if !Path::new(path).is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+
+        let mut found = Vec::new();
+        walk_dir(
+            Path::new(path),
+            Path::new(path),
+            include,
+            exclude,
+            &mut found,
+        );
+        found.sort();
+        files.extend(found);
+    }
+
+    files
+}
+
+fn walk_dir(
+    root: &Path,
+    dir: &Path,
+    include: &[String],
+    exclude: &[String],
+    found: &mut Vec<String>,
+) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("Error: can't read directory '{}', {:?}", dir.display(), err);
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+
+        if file_type.is_dir() {
+            walk_dir(root, &path, include, exclude, found);
+            continue;
+        }
+        if !path.is_file() {
+            continue;
+        }
+
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let included = include.iter().any(|g| path_matches(g, &relative));
+        let excluded = exclude.iter().any(|g| path_matches(g, &relative));
+        if included && !excluded {
+            found.push(path.to_string_lossy().to_string());
+        }
+    }
+}
+
+// Globs without a '/' are matched against the file name alone, so `*.plt`
+// picks up files in every nested folder. Globs with a '/' are matched
+// against the path relative to the walked directory.
+fn path_matches(glob: &str, relative: &str) -> bool {
+    if glob.contains('/') {
+        return glob_matches(glob, relative);
+    }
+    let file_name = relative.rsplit('/').next().unwrap_or(relative);
+    glob_matches(glob, file_name)
+}
+
+// Supports `*` (anything except '/'), `**` (anything, including '/') and `?`
+// (a single character other than '/').
+pub fn glob_matches(glob: &str, text: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_matches_at(&glob, &text)
+}
+
+fn glob_matches_at(glob: &[char], text: &[char]) -> bool {
+    match glob.first() {
+        None => text.is_empty(),
+        Some('*') if glob.get(1) == Some(&'*') => {
+            let rest = &glob[2..];
+            // `**/` also matches zero directories.
+            if rest.first() == Some(&'/') && glob_matches_at(&rest[1..], text) {
+                return true;
+            }
+            (0..=text.len()).any(|i| glob_matches_at(rest, &text[i..]))
+        }
+        Some('*') => {
+            let rest = &glob[1..];
+            for i in 0..=text.len() {
+                if glob_matches_at(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => match text.first() {
+            Some(c) if *c != '/' => glob_matches_at(&glob[1..], &text[1..]),
+            _ => false,
+        },
+        Some(c) => text.first() == Some(c) && glob_matches_at(&glob[1..], &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_matches, path_matches};
+
+    #[test]
+    fn star_stays_within_a_directory() {
+        assert!(glob_matches("*.plt", "print.plt"));
+        assert!(glob_matches("*.plt", ".plt"));
+        assert!(!glob_matches("*.plt", "print.plt.bak"));
+        assert!(!glob_matches("*.plt", "lexer/print.plt"));
+        assert!(glob_matches("lexer/*.plt", "lexer/print.plt"));
+    }
+
+    #[test]
+    fn double_star_crosses_directories() {
+        assert!(glob_matches("**/*.plt", "print.plt"));
+        assert!(glob_matches("**/*.plt", "a/b/print.plt"));
+        assert!(glob_matches("a/**/x.plt", "a/x.plt"));
+        assert!(glob_matches("a/**/x.plt", "a/b/c/x.plt"));
+        assert!(!glob_matches("a/**/x.plt", "b/x.plt"));
+        assert!(glob_matches("slow/**", "slow/a/b.plt"));
+    }
+
+    #[test]
+    fn question_mark_is_one_character() {
+        assert!(glob_matches("t?.plt", "t1.plt"));
+        assert!(!glob_matches("t?.plt", "t12.plt"));
+        assert!(!glob_matches("a?b", "a/b"));
+    }
+
+    #[test]
+    fn globs_without_slash_match_the_file_name() {
+        assert!(path_matches("*.plt", "nested/dir/print.plt"));
+        assert!(!path_matches("nested/*.plt", "nested/dir/print.plt"));
+        assert!(path_matches("nested/**/*.plt", "nested/dir/print.plt"));
+    }
+}
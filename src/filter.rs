@@ -0,0 +1,288 @@
+// A small regular expression matcher for selecting tests by name. Supports
+// literals, '.', '^', '$', classes like '[a-z_]', '[^0-9]', '\d', '\w' and
+// '\s', the repetitions '*', '+' and '?', '\' escapes and top-level
+// alternation with '|'. Patterns match anywhere in the name, so a plain word
+// is a substring search. Groups and counted repetitions aren't supported, so
+// '(', ')', '{' and '}' are rejected unless escaped.
+pub struct Pattern {
+    alternatives: Vec<Vec<Node>>,
+}
+
+struct Node {
+    atom: Atom,
+    repeat: Repeat,
+}
+
+enum Atom {
+    Char(char),
+    Any,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+    Start,
+    End,
+}
+
+#[derive(Clone, Copy)]
+enum Repeat {
+    One,
+    ZeroOrMore,
+    OneOrMore,
+    ZeroOrOne,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Option<Pattern> {
+        let mut alternatives = Vec::new();
+        for alternative in split_alternatives(pattern) {
+            match parse_nodes(&alternative) {
+                Ok(nodes) => alternatives.push(nodes),
+                Err(reason) => {
+                    eprintln!("Error: invalid pattern '{pattern}', {reason}");
+                    return None;
+                }
+            }
+        }
+        Some(Pattern { alternatives })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        self.alternatives
+            .iter()
+            .any(|nodes| (0..=text.len()).any(|start| match_here(nodes, &text, start)))
+    }
+}
+
+fn split_alternatives(pattern: &str) -> Vec<String> {
+    let mut alternatives = vec![String::new()];
+    let mut in_class = false;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        let current = alternatives.last_mut().unwrap();
+        match c {
+            '\\' => {
+                current.push(c);
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+            }
+            '[' => {
+                in_class = true;
+                current.push(c);
+            }
+            ']' => {
+                in_class = false;
+                current.push(c);
+            }
+            '|' if !in_class => alternatives.push(String::new()),
+            _ => current.push(c),
+        }
+    }
+    alternatives
+}
+
+fn parse_nodes(pattern: &str) -> Result<Vec<Node>, String> {
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let atom = match c {
+            '.' => Atom::Any,
+            '^' => Atom::Start,
+            '$' => Atom::End,
+            '\\' => match chars.next() {
+                Some('d') => class(&[('0', '9')]),
+                Some('w') => class(&[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')]),
+                Some('s') => class(&[(' ', ' '), ('\t', '\t'), ('\n', '\n'), ('\r', '\r')]),
+                Some(escaped) => Atom::Char(escaped),
+                None => return Err("it ends with a '\\'".to_string()),
+            },
+            '[' => parse_class(&mut chars)?,
+            '(' | ')' | '{' | '}' => {
+                return Err(format!(
+                    "'{c}' isn't supported, write '\\{c}' to match it literally"
+                ))
+            }
+            '*' | '+' | '?' => {
+                let repeat = match c {
+                    '*' => Repeat::ZeroOrMore,
+                    '+' => Repeat::OneOrMore,
+                    _ => Repeat::ZeroOrOne,
+                };
+                match nodes.last_mut() {
+                    Some(node) if matches!(node.repeat, Repeat::One) => {
+                        node.repeat = repeat;
+                        continue;
+                    }
+                    _ => return Err(format!("'{c}' doesn't follow anything to repeat")),
+                }
+            }
+            c => Atom::Char(c),
+        };
+        nodes.push(Node {
+            atom,
+            repeat: Repeat::One,
+        });
+    }
+
+    Ok(nodes)
+}
+
+fn class(ranges: &[(char, char)]) -> Atom {
+    Atom::Class {
+        negated: false,
+        ranges: ranges.to_vec(),
+    }
+}
+
+fn parse_class(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Atom, String> {
+    let negated = chars.peek() == Some(&'^');
+    if negated {
+        chars.next();
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = match chars.next() {
+            None => return Err("a '[' is never closed".to_string()),
+            Some(']') if !first => break,
+            Some('\\') => chars.next().ok_or("it ends with a '\\'")?,
+            Some(c) => c,
+        };
+        first = false;
+
+        let mut lookahead = chars.clone();
+        if lookahead.next() == Some('-') && !matches!(lookahead.next(), None | Some(']')) {
+            chars.next();
+            let end = chars.next().unwrap_or(c);
+            ranges.push((c, end));
+        } else {
+            ranges.push((c, c));
+        }
+    }
+
+    Ok(Atom::Class { negated, ranges })
+}
+
+fn match_here(nodes: &[Node], text: &[char], i: usize) -> bool {
+    let Some(node) = nodes.first() else {
+        return true;
+    };
+    let rest = &nodes[1..];
+
+    match node.repeat {
+        Repeat::One => {
+            match_atom(&node.atom, text, i).is_some_and(|next| match_here(rest, text, next))
+        }
+        Repeat::ZeroOrOne => {
+            match_atom(&node.atom, text, i).is_some_and(|next| match_here(rest, text, next))
+                || match_here(rest, text, i)
+        }
+        Repeat::ZeroOrMore | Repeat::OneOrMore => {
+            // Greedy: collect every position the atom can reach, then try
+            // the rest of the pattern from the longest match back.
+            let mut positions = vec![i];
+            while let Some(next) = match_atom(&node.atom, text, *positions.last().unwrap()) {
+                if next == *positions.last().unwrap() {
+                    break;
+                }
+                positions.push(next);
+            }
+            let min = if let Repeat::OneOrMore = node.repeat {
+                1
+            } else {
+                0
+            };
+            positions
+                .iter()
+                .skip(min)
+                .rev()
+                .any(|&position| match_here(rest, text, position))
+        }
+    }
+}
+
+// Returns the position after the atom if it matches at `i`.
+fn match_atom(atom: &Atom, text: &[char], i: usize) -> Option<usize> {
+    match atom {
+        Atom::Start => (i == 0).then_some(i),
+        Atom::End => (i == text.len()).then_some(i),
+        Atom::Any => text.get(i).map(|_| i + 1),
+        Atom::Char(c) => (text.get(i) == Some(c)).then_some(i + 1),
+        Atom::Class { negated, ranges } => {
+            let c = text.get(i)?;
+            let in_class = ranges.iter().any(|(lo, hi)| lo <= c && c <= hi);
+            (in_class != *negated).then_some(i + 1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pattern;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Pattern::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn plain_words_match_anywhere() {
+        assert!(matches("lex", "lexer errors"));
+        assert!(matches("errors", "lexer errors"));
+        assert!(!matches("parse", "lexer errors"));
+    }
+
+    #[test]
+    fn anchors() {
+        assert!(matches("^lex", "lexer"));
+        assert!(!matches("^lex", "a lexer"));
+        assert!(matches("er$", "lexer"));
+        assert!(!matches("er$", "lexers"));
+        assert!(matches("^$", ""));
+    }
+
+    #[test]
+    fn classes_and_escapes() {
+        assert!(matches("test_[0-9]+", "test_42"));
+        assert!(!matches("test_[0-9]+", "test_x"));
+        assert!(matches("[^a-z]", "abc1"));
+        assert!(!matches("^[^a-z]+$", "abc"));
+        assert!(matches(r"\d\s\w", "1 a"));
+        assert!(matches(r"a\.b", "a.b"));
+        assert!(!matches(r"a\.b", "axb"));
+        assert!(matches("[-a]", "-"));
+        assert!(matches("[]]", "]"));
+    }
+
+    #[test]
+    fn repetitions() {
+        assert!(matches("^ab*c$", "ac"));
+        assert!(matches("^ab*c$", "abbbc"));
+        assert!(!matches("^ab+c$", "ac"));
+        assert!(matches("^ab?c$", "abc"));
+        assert!(!matches("^ab?c$", "abbc"));
+        assert!(matches("^a.*z$", "a to z"));
+    }
+
+    #[test]
+    fn alternation() {
+        assert!(matches("lex|parse", "parser"));
+        assert!(matches("^lex$|^parse$", "lex"));
+        assert!(!matches("^lex$|^parse$", "lexer"));
+        assert!(matches("[|]", "|"));
+    }
+
+    #[test]
+    fn unsupported_syntax_is_rejected() {
+        assert!(Pattern::new("(lex|parse)").is_none());
+        assert!(Pattern::new("a{2}").is_none());
+        assert!(Pattern::new("*a").is_none());
+        assert!(Pattern::new("[abc").is_none());
+        assert!(Pattern::new("a\\").is_none());
+        assert!(matches(r"\(lex\)", "(lex)"));
+        assert!(matches("[(]", "("));
+    }
+}
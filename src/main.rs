@@ -1,283 +1,2266 @@
-use std::{self, io::Write, process::Stdio, str::Chars};
+mod bless;
+mod diagnostic;
+mod diff;
+mod discover;
+mod filter;
+
+use std::{
+    self,
+    fmt::Write as _,
+    io::{IsTerminal, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    process::{ExitCode, Stdio},
+    str::Chars,
+    time::{Duration, Instant},
+};
 
 struct Parser<'a> {
+    path: &'a str,
+    source: &'a str,
     chars: Chars<'a>,
     line: usize,
+    diagnostics: Vec<diagnostic::Diagnostic>,
 }
 
 struct Test {
     name: String,
     input: String,
-    expected: String,
+    // Extra files as (path, content), written next to the input file.
+    files: Vec<(String, String)>,
+    // The file passed to the command instead of the input, if any.
+    entry: Option<usize>,
+    // Not checked when a test with named sections has no 'STDOUT:' section.
+    expected: Option<String>,
+    // Set when the expected output is declared as raw bytes with 'EXPECTED:'.
+    expected_bytes: Option<Vec<u8>>,
+    expected_format: ExpectedFormat,
+    expected_span: Option<Span>,
+    expected_stderr: Option<String>,
+    stderr_span: Option<Span>,
+    stdin: Option<String>,
+    // Passed to the command after the test file.
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    clear_env: Option<bool>,
+    status: ExpectedStatus,
+    timeout: Option<Duration>,
+    final_newline: FinalNewline,
+    // Reasons given with the 'SKIP' and 'XFAIL' markers, empty if none.
+    skip: Option<String>,
+    xfail: Option<String>,
+    only: bool,
     line: usize,
 }
 
+struct RunResult {
+    stdout: Vec<u8>,
+    stderr: String,
+    status: std::process::ExitStatus,
+}
+
+// Where a block's content is in the source file, so it can be rewritten.
+struct Span {
+    range: Range<usize>,
+    separator: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ExpectedFormat {
+    Text,
+    Hex,
+    Escaped,
+}
+
+// What happens to the line break before the closing separator of a block.
+#[derive(Clone, Copy, PartialEq)]
+enum FinalNewline {
+    // Part of the block, like every other line break in it (the default).
+    Keep,
+    // Removed, for programs whose output doesn't end with a newline.
+    Strip,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Bless {
+    Off,
+    All,
+    Interactive,
+}
+
+enum ExpectedStatus {
+    Any,
+    Nonzero,
+    Code(i32),
+}
+
 struct TestsData {
+    path: String,
+    // Directory the tests of this file create their workspaces in.
+    work_dir: PathBuf,
     tests: Vec<Test>,
-    command: String,
+    command: Vec<String>,
+    timeout: Duration,
+    // File-level 'ARGS:' and 'ENV:', the ones of a test are added after them.
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    clear_env: bool,
+    // Set by --clear-env, which 'CLEAR_ENV: false' can't turn off.
+    force_clear_env: bool,
+    escape: bool,
+    // Number of tests left out by --filter, --exclude, --line or 'ONLY'.
+    filtered: usize,
+    // Number of parse errors in the file, only run with --run-partial.
+    parse_errors: usize,
+}
+
+struct Options {
+    // Already split into words, the command of a file is split when parsing it.
+    command: Option<Vec<String>>,
+    timeout: Duration,
+    jobs: usize,
+    work_dir: Option<PathBuf>,
+    keep_temp: bool,
+    escape: bool,
+    bless: Bless,
+    filters: Vec<filter::Pattern>,
+    excludes: Vec<filter::Pattern>,
+    lines: Vec<usize>,
+    paths: Vec<String>,
+    include_files: Vec<String>,
+    exclude_files: Vec<String>,
+    run_partial: bool,
+    clear_env: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            command: None,
+            timeout: DEFAULT_TIMEOUT,
+            jobs: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            work_dir: None,
+            keep_temp: false,
+            escape: false,
+            bless: Bless::Off,
+            filters: Vec::new(),
+            excludes: Vec::new(),
+            lines: Vec::new(),
+            paths: Vec::new(),
+            include_files: Vec::new(),
+            exclude_files: Vec::new(),
+            run_partial: false,
+            clear_env: false,
+        }
+    }
+}
+
+struct FailedTest {
+    path: String,
+    name: String,
+    line: usize,
+    outcome: Outcome,
+}
+
+#[derive(Default)]
+struct Summary {
+    passed: usize,
+    // Includes tests that unexpectedly passed despite an 'XFAIL' marker.
+    failed: Vec<FailedTest>,
+    skipped: Vec<SkippedTest>,
+    xfailed: usize,
+    filtered: usize,
+    errors: usize,
+    parse_errors: usize,
+}
+
+struct SkippedTest {
+    path: String,
+    name: String,
+    line: usize,
+    reason: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Outcome {
+    Passed,
+    Failed,
+    // The program was killed because it ran longer than the test's timeout.
+    Timeout,
+    // The program printed invalid UTF-8 where text output was expected.
+    InvalidUtf8,
+    // Not run because of a 'SKIP' marker.
+    Skipped,
+    // Failed as expected because of an 'XFAIL' marker.
+    XFailed,
+    // Passed despite an 'XFAIL' marker, which should be removed.
+    XPassed,
+    // The test could not be run at all, e.g. the command failed to spawn.
+    Error,
+}
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const EXIT_PASSED: u8 = 0;
+const EXIT_FAILED: u8 = 1;
+const EXIT_ERROR: u8 = 2;
+const EXIT_INTERNAL_ERROR: u8 = 3;
+
+// Printed after an invalid argument, the full USAGE is only shown by --help.
+const SHORT_USAGE: &str = "\
+Usage: pl-tester [OPTIONS] [COMMAND] <PATH>...
+Run 'pl-tester --help' for the options and the .plt file format.";
+
+const USAGE: &str = "\
+Usage: pl-tester [OPTIONS] [COMMAND] <PATH>...
+
+Each PATH is either a .plt file or a directory that is searched recursively.
+COMMAND overrides the 'COMMAND:' directive at the top of each .plt file.
+
+A block of a test starts on the line after its opening separator, e.g. '---',
+and ends before the next line that holds nothing but the separator. The text
+in between is kept exactly, including leading whitespace and the line break of
+its last line. 'FINAL_NEWLINE: strip' at the top of a file or in a test drops
+that last line break, 'FINAL_NEWLINE: keep' is the default.
+
+A block opened with '<<<NAME' instead ends before the line holding just NAME,
+so it may contain lines like '---'. The next block of the test is then opened
+on a line of its own again.
+
+Instead of the two blocks for the program and its expected output, a test can
+declare named sections in any order: 'INPUT:' and 'STDOUT:' blocks, 'ARGS:'
+with more arguments for the command, 'ENV: NAME=VALUE' for an environment
+variable, and the 'STDIN:', 'STDERR:' and 'STATUS:' sections that also work
+with the two-block form. Output without a section isn't checked.
+
+'ARGS:' and 'ENV:' can also be written at the top of a file, and apply to all
+of its tests before their own. 'CLEAR_ENV: true' in a file or a test runs the
+command with no other environment variables than those from 'ENV:'.
+
+'FILE: PATH' sections add more files to a test, each followed by its block.
+They're written to the test's temporary directory, and the first one is
+passed to the command unless another file is declared with 'ENTRY: PATH' or
+the test has an 'INPUT:' section.
+
+Every test runs in its temporary directory. A program in COMMAND given by a
+relative path containing a '/' is still found from the directory pl-tester
+was started in, other words of COMMAND and 'ARGS:' are passed as written.
+Paths to other files of that directory are written with {cwd}, e.g.
+'cargo run -q --manifest-path {cwd}/Cargo.toml --'.
+
+COMMAND is split into words like a shell would do it, and may contain the
+placeholders {file}, {dir}, {name}, {line} and {cwd}, which are replaced with
+the path of the generated source file, its directory, its file name, the line
+of the test and the directory pl-tester was started in. Unless {file}, {dir}
+or {name} is used, the file path is appended at the end, {line} and {cwd}
+alone don't count.
+
+Options:
+  -c, --command <CMD>     same as passing COMMAND
+  -t, --timeout <TIME>    default time limit of a test, e.g. 5, 2.5s or 500ms,
+                          0 disables it (default: 10s). Overridden by the
+                          'TIMEOUT:' directive of a file or a test
+  -j, --jobs <N>          number of tests to run at once (default: number of CPUs)
+  --work-dir <DIR>        create the temporary directory of the run in DIR
+                          (default: $TMPDIR or /tmp)
+  --keep-temp             don't delete the temporary files after the run
+  --escape                show whitespace and control characters in diffs as
+                          escapes, done automatically when outputs only differ
+                          in whitespace
+  --bless, --update       rewrite the expected output of failing tests in the
+                          .plt files with the actual output
+  --bless-interactive     like --bless, but ask about every failing test
+  --filter <PATTERN>      run only tests whose name matches PATTERN, a substring
+                          or a simple regular expression with . ^ $ [...] \\d
+                          \\w \\s * + ? and |, but no groups or {n}
+  --exclude <PATTERN>     don't run tests whose name matches PATTERN
+  --line <N>              run only the test declared around line N
+  --run-partial           run the tests of a file that parsed correctly even
+                          if other tests in it have parse errors
+  --clear-env             run the tests with only the environment variables set
+                          by their 'ENV:' directives, like 'CLEAR_ENV: true',
+                          even where 'CLEAR_ENV: false' is written
+  --include-files <GLOB>  run only files in directories matching GLOB (default: *.plt)
+  --exclude-files <GLOB>  skip files in directories matching GLOB
+
+Exit status:
+  0  all tests passed
+  1  some tests failed
+  2  invalid arguments, a suite could not be parsed or the command could not be run
+  3  internal error";
+
+// Temporary directory of the run, removed by the panic hook and after an
+// interrupt as well, since both skip the cleanup at the end of `main`. The
+// hook kills the running tests for the same reason.
+static RUN_DIR: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+
+fn main() -> ExitCode {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        kill_live_groups();
+        if let Some(run_dir) = RUN_DIR.get() {
+            let _ = std::fs::remove_dir_all(run_dir);
+        }
+        std::process::exit(EXIT_INTERNAL_ERROR.into());
+    }));
+    let Some(options) = parse_args() else {
+        eprintln!("{SHORT_USAGE}");
+        return ExitCode::from(EXIT_ERROR);
+    };
+    install_interrupt_handler(options.jobs);
+
+    let test_paths = discover::collect_test_files(
+        &options.paths,
+        &options.include_files,
+        &options.exclude_files,
+    );
+    if test_paths.is_empty() {
+        eprintln!("Error: no test files found");
+        return ExitCode::from(EXIT_ERROR);
+    }
+
+    let base_dir = options.work_dir.clone().unwrap_or_else(std::env::temp_dir);
+    let Some(run_dir) = create_run_dir(&base_dir) else {
+        return ExitCode::from(EXIT_INTERNAL_ERROR);
+    };
+    if !options.keep_temp {
+        let _ = RUN_DIR.set(run_dir.clone());
+    }
+
+    let mut total = Summary::default();
+    let mut bad_files = Vec::new();
+
+    // All files are parsed before running anything, so an 'ONLY' marker in
+    // one of them restricts the whole run.
+    let mut suites = Vec::new();
+    for (file_index, test_path) in test_paths.iter().enumerate() {
+        let stem = Path::new(test_path).file_stem().unwrap_or_default();
+        let work_dir = run_dir.join(format!("{file_index}-{}", stem.to_string_lossy()));
+        match load_suite(test_path, work_dir, &options) {
+            Some(suite) => suites.push(suite),
+            None => {
+                eprintln!("Error: skipping '{test_path}'");
+                bad_files.push(test_path);
+            }
+        }
+    }
+    if !bad_files.is_empty() {
+        eprintln!();
+    }
+    let only = suites
+        .iter()
+        .any(|(_, tests_data)| tests_data.tests.iter().any(|t| t.only));
+
+    for (source, mut tests_data) in suites {
+        println!("FILE {}:", tests_data.path);
+        select_tests(&mut tests_data, &options, only);
+        let summary = run_tests(tests_data, &source, &options);
+        total.passed += summary.passed;
+        total.failed.extend(summary.failed);
+        total.skipped.extend(summary.skipped);
+        total.xfailed += summary.xfailed;
+        total.filtered += summary.filtered;
+        total.errors += summary.errors;
+        total.parse_errors += summary.parse_errors;
+    }
+
+    if 1 < test_paths.len() {
+        print_total(&total, &bad_files);
+    }
+
+    if options.keep_temp {
+        println!("Temporary files are kept in {}", run_dir.display());
+    } else {
+        remove_temp_files(&run_dir);
+    }
+
+    if !bad_files.is_empty() || 0 < total.errors || 0 < total.parse_errors {
+        ExitCode::from(EXIT_ERROR)
+    } else if !total.failed.is_empty() {
+        ExitCode::from(EXIT_FAILED)
+    } else {
+        ExitCode::from(EXIT_PASSED)
+    }
+}
+
+fn parse_args() -> Option<Options> {
+    let mut options = Options::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" | "--command" => {
+                options.command = Some(parse_cli_command(&flag_value(&mut args, &arg)?)?)
+            }
+            "-j" | "--jobs" => options.jobs = parse_jobs(&flag_value(&mut args, &arg)?)?,
+            "--work-dir" => options.work_dir = Some(flag_value(&mut args, &arg)?.into()),
+            "--keep-temp" => options.keep_temp = true,
+            "--clear-env" => options.clear_env = true,
+            "--run-partial" => options.run_partial = true,
+            "--escape" => options.escape = true,
+            "--bless" | "--update" => options.bless = Bless::All,
+            "--bless-interactive" => options.bless = Bless::Interactive,
+            "--filter" => options
+                .filters
+                .push(filter::Pattern::new(&flag_value(&mut args, &arg)?)?),
+            "--exclude" => options
+                .excludes
+                .push(filter::Pattern::new(&flag_value(&mut args, &arg)?)?),
+            "--line" => options
+                .lines
+                .push(parse_line(&flag_value(&mut args, &arg)?)?),
+            "-t" | "--timeout" => {
+                let value = flag_value(&mut args, &arg)?;
+                options.timeout = parse_duration(&value)
+                    .map_err(|err| eprintln!("Error: {err}"))
+                    .ok()?;
+            }
+            "--include-files" => options.include_files.push(flag_value(&mut args, &arg)?),
+            "--exclude-files" => options.exclude_files.push(flag_value(&mut args, &arg)?),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(EXIT_PASSED.into());
+            }
+            _ if arg.starts_with('-') && arg != "-" => {
+                eprintln!("Error: unknown option '{arg}'");
+                return None;
+            }
+            _ if options.command.is_none() && options.paths.is_empty() && !is_test_path(&arg) => {
+                options.command = Some(parse_cli_command(&arg)?)
+            }
+            _ => options.paths.push(arg),
+        }
+    }
+
+    if options.paths.is_empty() {
+        eprintln!("Error: expected at least one test file or directory");
+        return None;
+    }
+    if options.include_files.is_empty() {
+        options
+            .include_files
+            .push(discover::DEFAULT_INCLUDE.to_string());
+    }
+
+    Some(options)
+}
+
+fn parse_cli_command(value: &str) -> Option<Vec<String>> {
+    match split_command(value) {
+        Ok(command) if !command.is_empty() => Some(command),
+        Ok(_) => {
+            eprintln!("Error: the command to run the tests with is empty");
+            None
+        }
+        Err(err) => {
+            eprintln!("Error: {err}");
+            None
+        }
+    }
+}
+
+// The command may be omitted when every suite declares its own 'COMMAND:',
+// so the first positional argument is only a command if it can't be a suite.
+fn is_test_path(arg: &str) -> bool {
+    arg.ends_with(".plt") || std::path::Path::new(arg).is_dir()
 }
 
-fn main() {
-    let mut arg_iter = std::env::args().skip(2);
+fn parse_line(value: &str) -> Option<usize> {
+    match value.parse() {
+        Ok(line) if 0 < line => Some(line),
+        _ => {
+            eprintln!("Error: invalid line number '{value}'");
+            None
+        }
+    }
+}
+
+fn parse_jobs(value: &str) -> Option<usize> {
+    match value.parse() {
+        Ok(jobs) if 0 < jobs => Some(jobs),
+        _ => {
+            eprintln!("Error: invalid number of jobs '{value}'");
+            None
+        }
+    }
+}
 
-    // TODO(sezoka): add option to escape output strings for error messages
-    // e.g. '  ' -> '\t'
+fn flag_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Option<String> {
+    let value = args.next();
+    if value.is_none() {
+        eprintln!("Error: expected a value after '{flag}'");
+    }
+    value
+}
 
-    while let Some(arg) = arg_iter.next() {
-        let test_path = arg;
-        parse_and_run(&test_path);
-        break;
+fn print_total(total: &Summary, bad_files: &[&String]) {
+    println!("TOTAL:");
+    print_counts(total);
+    if !total.skipped.is_empty() {
+        println!("SKIPPED TESTS:");
+        print_skipped_tests(&total.skipped);
+    }
+    if !total.failed.is_empty() {
+        println!("FAILED TESTS:");
+        print_failed_tests(&total.failed);
+    }
+    if !bad_files.is_empty() {
+        println!("Could not run {} file(s):", bad_files.len());
+        for path in bad_files {
+            println!("{path}");
+        }
     }
+    println!();
 }
 
-fn parse_and_run(path: &str) -> Option<()> {
+fn load_suite(path: &str, work_dir: PathBuf, options: &Options) -> Option<(String, TestsData)> {
     let file = read_file(path)?;
-    let tests_data = parse(file)?;
-    run_tests(tests_data)?;
-    remove_temp_files();
-    return Some(());
+    let mut tests_data = parse(path, &file, options)?;
+    tests_data.work_dir = work_dir;
+    resolve_program(&mut tests_data.command);
+    Some((file, tests_data))
+}
+
+// Tests run in their own directory, so a relative path to the program is
+// made absolute against the directory pl-tester was started in. A program
+// without a '/' is looked up in PATH, like a shell does it, and one with
+// placeholders like '{cwd}/interp' or './{name}' is left to them.
+fn resolve_program(command: &mut [String]) {
+    let Some(program) = command
+        .first_mut()
+        .filter(|program| program.contains('/') && !program.contains('{'))
+    else {
+        return;
+    };
+    if let Ok(path) = std::path::absolute(&program) {
+        *program = path.to_string_lossy().to_string();
+    }
+}
+
+fn select_tests(tests_data: &mut TestsData, options: &Options, only: bool) {
+    // A line selects the test whose 'TEST' line is the closest one above it.
+    let at_lines: Vec<usize> = options
+        .lines
+        .iter()
+        .filter_map(|&line| {
+            tests_data
+                .tests
+                .iter()
+                .map(|t| t.line)
+                .filter(|&test_line| test_line <= line)
+                .max()
+        })
+        .collect();
+
+    let count = tests_data.tests.len();
+    tests_data.tests.retain(|t| {
+        (options.filters.is_empty() || options.filters.iter().any(|f| f.is_match(&t.name)))
+            && !options.excludes.iter().any(|f| f.is_match(&t.name))
+            && (options.lines.is_empty() || at_lines.contains(&t.line))
+            && (!only || t.only)
+    });
+    tests_data.filtered = count - tests_data.tests.len();
+}
+
+// Every run gets a fresh directory, so concurrent runs never touch each
+// other's files.
+fn create_run_dir(base_dir: &Path) -> Option<PathBuf> {
+    // Tests don't run in the directory pl-tester was started in, so the paths
+    // passed to them must be absolute.
+    let base_dir = match std::path::absolute(base_dir) {
+        Ok(base_dir) => base_dir,
+        Err(err) => {
+            eprintln!(
+                "Error: can't resolve directory '{}', {:?}",
+                base_dir.display(),
+                err
+            );
+            return None;
+        }
+    };
+    if let Err(err) = std::fs::create_dir_all(&base_dir) {
+        eprintln!(
+            "Error: can't create directory '{}', {:?}",
+            base_dir.display(),
+            err
+        );
+        return None;
+    }
+
+    let pid = std::process::id();
+    for attempt in 0.. {
+        let run_dir = base_dir.join(format!("pltest-{pid}-{attempt}"));
+        match std::fs::create_dir(&run_dir) {
+            Ok(()) => return Some(run_dir),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => {
+                eprintln!(
+                    "Error: can't create temporary directory '{}', {:?}",
+                    run_dir.display(),
+                    err
+                );
+                return None;
+            }
+        }
+    }
+    None
 }
 
-fn remove_temp_files() {
-    std::fs::remove_dir_all("/tmp/pltest").unwrap();
+fn remove_temp_files(run_dir: &Path) {
+    if let Err(err) = std::fs::remove_dir_all(run_dir) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            eprintln!(
+                "Error: can't remove temporary directory '{}', {:?}",
+                run_dir.display(),
+                err
+            );
+        }
+    }
 }
 
-fn run_tests(tests_data: TestsData) -> Option<()> {
+fn run_tests(tests_data: TestsData, source: &str, options: &Options) -> Summary {
+    let filtered = if 0 < tests_data.filtered {
+        format!(" ({} FILTERED)", tests_data.filtered)
+    } else {
+        String::new()
+    };
     if 1 < tests_data.tests.len() {
-        println!("RUNNING {} TESTS:", tests_data.tests.len());
+        println!("RUNNING {} TESTS{filtered}:", tests_data.tests.len());
     } else {
-        println!("RUNNING {} TEST:", tests_data.tests.len());
+        println!("RUNNING {} TEST{filtered}:", tests_data.tests.len());
     }
     println!();
 
-    std::fs::create_dir_all("/tmp/pltest").unwrap();
+    let mut summary = Summary {
+        filtered: tests_data.filtered,
+        parse_errors: tests_data.parse_errors,
+        ..Summary::default()
+    };
+    let mut edits = Vec::new();
 
-    let mut failed_tests = Vec::new();
+    run_tests_in_parallel(&tests_data, options.jobs, |test, outcome, result| {
+        match outcome {
+            Outcome::Passed => summary.passed += 1,
+            Outcome::XFailed => summary.xfailed += 1,
+            Outcome::Skipped => summary.skipped.push(SkippedTest {
+                path: tests_data.path.clone(),
+                name: test.name.clone(),
+                line: test.line,
+                reason: test.skip.clone().unwrap_or_default(),
+            }),
+            _ => {
+                if outcome == Outcome::Error {
+                    summary.errors += 1;
+                }
+                summary.failed.push(FailedTest {
+                    path: tests_data.path.clone(),
+                    name: test.name.clone(),
+                    line: test.line,
+                    outcome,
+                });
+            }
+        }
+        if let (Outcome::Failed, Some(result)) = (outcome, result) {
+            if options.bless != Bless::Off {
+                let test_edits = bless_edits(test, &result);
+                if !test_edits.is_empty()
+                    && (options.bless == Bless::All || bless::confirm(&test.name, test.line))
+                {
+                    edits.extend(test_edits);
+                }
+            }
+        }
+    });
 
-    for test in tests_data.tests.iter() {
-        if run_test(&test, &tests_data).is_none() {
-            failed_tests.push((&test.name, test.line));
+    if !edits.is_empty() {
+        let count = edits.len();
+        let blessed = bless::apply(source, edits);
+        if let Err(err) = std::fs::write(&tests_data.path, blessed) {
+            eprintln!(
+                "Error: can't update test file '{}', {:?}",
+                tests_data.path, err
+            );
+        } else {
+            println!("\nUpdated {count} expected block(s) in {}", tests_data.path);
         }
     }
 
     println!();
-    if failed_tests.is_empty() {
+    if !summary.skipped.is_empty() {
+        println!("SKIPPED TESTS:");
+        print_skipped_tests(&summary.skipped);
+        println!();
+    }
+    if summary.failed.is_empty() {
         println!("All tests successfully completed!");
     } else {
         println!("FAILED TESTS:");
-        for (test_name, line) in &failed_tests {
-            println!("{} on line {}", test_name, line);
+        print_failed_tests(&summary.failed);
+        println!();
+    }
+    print_counts(&summary);
+    println!();
+
+    summary
+}
+
+fn print_skipped_tests(skipped_tests: &[SkippedTest]) {
+    for test in skipped_tests {
+        print!("{} at {}:{}", test.name, test.path, test.line);
+        if test.reason.is_empty() {
+            println!();
+        } else {
+            println!(" ({})", test.reason);
+        }
+    }
+}
+
+// Prints the counts of a file or of the whole run. Tests that ran are those
+// that passed, failed, or had an 'XFAIL' marker, skipped and filtered out
+// tests are counted separately.
+fn print_counts(summary: &Summary) {
+    let xpassed = summary
+        .failed
+        .iter()
+        .filter(|t| t.outcome == Outcome::XPassed)
+        .count();
+    let failed = summary.failed.len() - xpassed;
+    println!(
+        "Passed {} and failed {failed} out of {} tests.",
+        summary.passed,
+        summary.passed + failed + summary.xfailed + xpassed
+    );
+    let counts = [
+        (summary.skipped.len(), "skipped"),
+        (summary.xfailed, "failed as expected (XFAIL)"),
+        (xpassed, "unexpectedly passed (XPASS)"),
+        (summary.filtered, "filtered out"),
+    ];
+    let counts: Vec<String> = counts
+        .iter()
+        .filter(|(count, _)| 0 < *count)
+        .map(|(count, what)| format!("{count} {what}"))
+        .collect();
+    if !counts.is_empty() {
+        println!("Tests {}.", counts.join(", "));
+    }
+    if 0 < summary.parse_errors {
+        println!(
+            "Tests with {} parse error(s) were not run.",
+            summary.parse_errors
+        );
+    }
+}
+
+// Runs the tests on `jobs` worker threads. Reports are printed and passed to
+// `on_finished` in the order the tests are declared in, whatever order they
+// finish in.
+fn run_tests_in_parallel<'a>(
+    tests_data: &'a TestsData,
+    jobs: usize,
+    mut on_finished: impl FnMut(&'a Test, Outcome, Option<RunResult>),
+) {
+    let next_test = std::sync::atomic::AtomicUsize::new(0);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut next_to_report = 0;
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, tests_data.tests.len().max(1)) {
+            let sender = sender.clone();
+            let next_test = &next_test;
+            scope.spawn(move || loop {
+                let index = next_test.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let Some(test) = tests_data.tests.get(index) else {
+                    break;
+                };
+                let mut report = String::new();
+                let mut result = None;
+                let outcome = run_marked_test(&mut report, &mut result, test, tests_data);
+                if sender.send((index, outcome, report, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut finished = std::collections::BTreeMap::new();
+        for (index, outcome, report, result) in receiver {
+            finished.insert(index, (outcome, report, result));
+            while let Some((outcome, report, result)) = finished.remove(&next_to_report) {
+                print!("{report}");
+                on_finished(&tests_data.tests[next_to_report], outcome, result);
+                next_to_report += 1;
+            }
+        }
+    });
+}
+
+// Applies the 'SKIP' and 'XFAIL' markers of the test around `run_test`.
+fn run_marked_test(
+    out: &mut String,
+    actual: &mut Option<RunResult>,
+    t: &Test,
+    td: &TestsData,
+) -> Outcome {
+    if t.skip.is_some() {
+        return Outcome::Skipped;
+    }
+
+    let outcome = run_test(out, actual, t, td);
+    if t.xfail.is_none() {
+        return outcome;
+    }
+    match outcome {
+        Outcome::Passed => {
+            let _ = writeln!(
+                out,
+                "![{}]({}): unexpectedly passed, remove its 'XFAIL' marker",
+                t.line, t.name
+            );
+            Outcome::XPassed
+        }
+        Outcome::Failed | Outcome::Timeout | Outcome::InvalidUtf8 => {
+            // The failure is known, so its report would only be noise.
+            out.clear();
+            *actual = None;
+            Outcome::XFailed
+        }
+        outcome => outcome,
+    }
+}
+
+// Edits that replace the mismatching expected blocks of the test with the
+// actual output. Blocks that couldn't be read back unchanged are left alone.
+fn bless_edits(t: &Test, result: &RunResult) -> Vec<bless::Edit> {
+    let mut edits = Vec::new();
+
+    if let (Some(expected), Some(span)) = (expected_stdout(t), &t.expected_span) {
+        if result.stdout != expected {
+            let text = match t.expected_format {
+                ExpectedFormat::Text => String::from_utf8(result.stdout.clone()).ok(),
+                ExpectedFormat::Hex => Some(bless::to_hex_block(&result.stdout)),
+                ExpectedFormat::Escaped => Some(bless::to_escaped_block(&result.stdout)),
+            };
+            match text {
+                Some(text) => push_bless_edit(&mut edits, t, span, text),
+                None => eprintln!(
+                    "Error: can't update '{}' on line {}, the output isn't valid UTF-8, use 'EXPECTED: hex'",
+                    t.name, t.line
+                ),
+            }
+        }
+    }
+
+    if let (Some(expected), Some(span)) = (&t.expected_stderr, &t.stderr_span) {
+        if &result.stderr != expected {
+            push_bless_edit(&mut edits, t, span, result.stderr.clone());
+        }
+    }
+
+    edits
+}
+
+fn push_bless_edit(edits: &mut Vec<bless::Edit>, t: &Test, span: &Span, mut text: String) {
+    if t.final_newline == FinalNewline::Strip {
+        text.push('\n');
+    }
+    match bless::check_block_text(&text, &span.separator) {
+        Ok(()) => edits.push(bless::Edit {
+            span: span.range.clone(),
+            text,
+        }),
+        Err(reason) => eprintln!(
+            "Error: can't update '{}' on line {}, {reason}",
+            t.name, t.line
+        ),
+    }
+}
+
+fn print_failed_tests(failed_tests: &[FailedTest]) {
+    for test in failed_tests {
+        let label = match test.outcome {
+            Outcome::Timeout => " (TIMEOUT)",
+            Outcome::InvalidUtf8 => " (INVALID UTF-8)",
+            Outcome::XPassed => " (UNEXPECTEDLY PASSED)",
+            Outcome::Error => " (ERROR)",
+            _ => "",
+        };
+        println!("{} at {}:{}{label}", test.name, test.path, test.line);
+    }
+}
+
+// Failure details are written to `out` instead of stdout, so tests running in
+// parallel don't mix their reports.
+fn run_test(out: &mut String, actual: &mut Option<RunResult>, t: &Test, td: &TestsData) -> Outcome {
+    // Test lines are unique within a file, so every test gets its own
+    // directory even if the names of two tests only differ in case.
+    let test_dir = td.work_dir.join(t.line.to_string());
+    let test_file_path = match t.entry {
+        Some(entry) => test_dir.join(&t.files[entry].0),
+        None => test_dir.join(test_file_name(&t.name)),
+    };
+    let test_file_path = test_file_path.to_string_lossy().to_string();
+    let cmd_str = td.command.join(" ");
+
+    if let Err(err) = std::fs::create_dir_all(&test_dir) {
+        let message = format!(
+            "can't create test directory at '{}', {err}",
+            test_dir.display()
+        );
+        print_error(out, &message, t);
+        return Outcome::Error;
+    }
+
+    for (path, content) in &t.files {
+        let path = test_dir.join(path);
+        let written = match path.parent() {
+            Some(dir) => std::fs::create_dir_all(dir),
+            None => Ok(()),
+        };
+        if let Err(err) = written.and_then(|()| std::fs::write(&path, content)) {
+            let message = format!("can't create test file at '{}', {err}", path.display());
+            print_error(out, &message, t);
+            return Outcome::Error;
+        }
+    }
+
+    if t.entry.is_none() {
+        let mut file = match std::fs::File::create(&test_file_path) {
+            Ok(file) => file,
+            Err(err) => {
+                let message = format!("can't create test file at '{test_file_path}', {err}");
+                print_error(out, &message, t);
+                return Outcome::Error;
+            }
+        };
+        if let Err(err) = file.write_all(t.input.as_bytes()) {
+            let message = format!("can't write test input to '{test_file_path}', {err}");
+            print_error(out, &message, t);
+            return Outcome::Error;
+        }
+    }
+
+    let mut argv = expand_command(&td.command, t, &test_file_path);
+    argv.extend(td.args.iter().chain(&t.args).cloned());
+    let env: Vec<&(String, String)> = td.env.iter().chain(&t.env).collect();
+    let clear_env = td.force_clear_env || t.clear_env.unwrap_or(td.clear_env);
+    // Tests run in their directory, so their files can refer to each other by
+    // relative paths. See `resolve_program` for the program's path.
+    let mut cmd = std::process::Command::new(&argv[0]);
+    cmd.args(&argv[1..]);
+    cmd.current_dir(&test_dir);
+    if clear_env {
+        cmd.env_clear();
+    }
+    cmd.envs(env.iter().map(|(name, value)| (name, value)));
+    // Tests with their own arguments or environment show how they were run
+    // when they fail, so the run can be repeated by hand.
+    let invocation = (t.args.len() + td.args.len() + env.len() != 0 || clear_env)
+        .then(|| format_invocation(&argv, &env, clear_env));
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    if t.stdin.is_some() {
+        cmd.stdin(Stdio::piped());
+    } else {
+        cmd.stdin(Stdio::null());
+    }
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+
+    let timeout = t.timeout.unwrap_or(td.timeout);
+
+    match cmd.spawn() {
+        Ok(child) => match wait_with_timeout(write_stdin(child, t), timeout) {
+            Ok((output, timed_out)) => {
+                let result = RunResult {
+                    stdout: output.stdout,
+                    stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+                    status: output.status,
+                };
+                if timed_out {
+                    print_timeout(out, &result, timeout, t);
+                    print_invocation(out, &invocation);
+                    return Outcome::Timeout;
+                }
+                if t.expected.is_some() && t.expected_bytes.is_none() {
+                    if let Err(err) = std::str::from_utf8(&result.stdout) {
+                        print_invalid_utf8(out, &result.stdout, err, t);
+                        print_invocation(out, &invocation);
+                        return Outcome::InvalidUtf8;
+                    }
+                }
+                if !results_as_expected(out, &result, t, td.escape) {
+                    print_invocation(out, &invocation);
+                    *actual = Some(result);
+                    return Outcome::Failed;
+                }
+            }
+            Err(err) => {
+                print_error(out, &format!("can't wait for '{cmd_str}', {err}"), t);
+                return Outcome::Error;
+            }
+        },
+        Err(err) => {
+            print_error(out, &format!("'{cmd_str}' failed to run, {err}"), t);
+            return Outcome::Error;
+        }
+    }
+
+    Outcome::Passed
+}
+
+// Stdin is written from a separate thread, so a program that prints a lot
+// before reading its input can't deadlock with us.
+fn write_stdin(mut child: std::process::Child, t: &Test) -> std::process::Child {
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), t.stdin.clone()) {
+        std::thread::spawn(move || {
+            // The program may exit without reading all of its input.
+            let _ = stdin.write_all(input.as_bytes());
+        });
+    }
+    child
+}
+
+// Like `Child::wait_with_output`, but kills the process group of the child
+// once `timeout` is over. A zero timeout waits forever. Output read before the
+// kill is kept. The group is also killed when the child exits by itself, so
+// background processes it left behind can't hold its pipes open.
+fn wait_with_timeout(
+    mut child: std::process::Child,
+    timeout: Duration,
+) -> std::io::Result<(std::process::Output, bool)> {
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+    let slot = track_process_group(&mut child)?;
+
+    let start = Instant::now();
+    let mut timed_out = false;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Ok(status),
+            Ok(None) => {}
+            Err(err) => break Err(err),
+        }
+        if !timeout.is_zero() && timeout <= start.elapsed() {
+            timed_out = true;
+            kill_process_group(&mut child);
+            break child.wait();
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    };
+    // The group id can't be reused while any process of the group is alive,
+    // so this only reaches what the test started.
+    kill_process_group(&mut child);
+    untrack_process_group(slot);
+    let status = status?;
+
+    let output = std::process::Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    };
+    Ok((output, timed_out))
+}
+
+fn read_in_background(
+    pipe: Option<impl Read + Send + 'static>,
+) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+#[cfg(unix)]
+extern "C" {
+    fn kill(pid: i32, sig: i32) -> i32;
+    fn signal(signum: i32, handler: usize) -> usize;
+    fn raise(sig: i32) -> i32;
+}
+
+#[cfg(unix)]
+const SIGINT: i32 = 2;
+#[cfg(unix)]
+const SIGKILL: i32 = 9;
+#[cfg(unix)]
+const SIGTERM: i32 = 15;
+
+// Process groups of the running tests. Test processes are taken out of the
+// terminal's process group, so Ctrl-C doesn't reach them and they are killed
+// by the interrupt handler instead. There is a slot for each of the --jobs
+// tests that run at once, and the slots are atomics because they're read in
+// the signal handler.
+#[cfg(unix)]
+static LIVE_GROUPS: std::sync::OnceLock<Box<[std::sync::atomic::AtomicI32]>> =
+    std::sync::OnceLock::new();
+
+#[cfg(unix)]
+fn install_interrupt_handler(jobs: usize) {
+    let slots = (0..jobs)
+        .map(|_| std::sync::atomic::AtomicI32::new(0))
+        .collect();
+    let _ = LIVE_GROUPS.set(slots);
+    std::thread::spawn(watch_interrupt);
+    unsafe {
+        signal(SIGINT, on_interrupt as extern "C" fn(i32) as usize);
+        signal(SIGTERM, on_interrupt as extern "C" fn(i32) as usize);
+    }
+}
+
+#[cfg(not(unix))]
+fn install_interrupt_handler(_jobs: usize) {}
+
+// Signal that interrupted the run, 0 until then.
+#[cfg(unix)]
+static INTERRUPTED: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(0);
+
+// Kills the process groups of all running tests. Removing the temporary files
+// isn't safe in a signal handler, so `watch_interrupt` does the rest.
+#[cfg(unix)]
+extern "C" fn on_interrupt(sig: i32) {
+    INTERRUPTED.store(sig, std::sync::atomic::Ordering::SeqCst);
+    kill_live_groups();
+}
+
+// Runs on its own thread, since the main thread may be waiting for tests or
+// for an answer to --bless-interactive. Once interrupted, it removes the
+// temporary directory and dies of the signal the way pl-tester would have
+// without the handler.
+#[cfg(unix)]
+fn watch_interrupt() {
+    let sig = loop {
+        let sig = INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst);
+        if sig != 0 {
+            break sig;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    kill_live_groups();
+    if let Some(run_dir) = RUN_DIR.get() {
+        let _ = std::fs::remove_dir_all(run_dir);
+    }
+    unsafe {
+        signal(sig, 0);
+        raise(sig);
+    }
+}
+
+#[cfg(unix)]
+fn kill_live_groups() {
+    for slot in LIVE_GROUPS.get().into_iter().flatten() {
+        let group = slot.load(std::sync::atomic::Ordering::SeqCst);
+        if group != 0 {
+            unsafe {
+                kill(-group, SIGKILL);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_live_groups() {}
+
+// A group that can't be tracked would survive an interrupt, so the test is
+// killed right away instead.
+#[cfg(unix)]
+fn track_process_group(child: &mut std::process::Child) -> std::io::Result<Option<usize>> {
+    use std::sync::atomic::Ordering;
+    let group = child.id() as i32;
+    let slot = LIVE_GROUPS.get().and_then(|slots| {
+        slots.iter().position(|slot| {
+            slot.compare_exchange(0, group, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        })
+    });
+    if slot.is_none() {
+        kill_process_group(child);
+        let _ = child.wait();
+        return Err(std::io::Error::other(
+            "no free slot to track the test's process group",
+        ));
+    }
+    // A test started while the handler was killing the others.
+    if INTERRUPTED.load(Ordering::SeqCst) != 0 {
+        kill_process_group(child);
+    }
+    Ok(slot)
+}
+
+#[cfg(not(unix))]
+fn track_process_group(_child: &mut std::process::Child) -> std::io::Result<Option<usize>> {
+    Ok(None)
+}
+
+#[cfg(unix)]
+fn untrack_process_group(slot: Option<usize>) {
+    if let (Some(slot), Some(slots)) = (slot, LIVE_GROUPS.get()) {
+        slots[slot].store(0, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(not(unix))]
+fn untrack_process_group(_slot: Option<usize>) {}
+
+#[cfg(unix)]
+fn kill_process_group(child: &mut std::process::Child) {
+    // The child was spawned as the leader of its own process group, so this
+    // also kills everything it started.
+    unsafe {
+        kill(-(child.id() as i32), SIGKILL);
+    }
+    let _ = child.kill();
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(child: &mut std::process::Child) {
+    let _ = child.kill();
+}
+
+// The input file of a test is named after it, with path separators and
+// leading dots replaced so that it stays in the test's directory, like
+// 'lexer/strings' or '..'.
+fn test_file_name(name: &str) -> String {
+    let rest = name.trim_start_matches('.');
+    let dots = "_".repeat(name.len() - rest.len());
+    let rest = rest.chars().map(|c| {
+        if c.is_whitespace() || c == '/' || c == '\\' {
+            '_'
+        } else {
+            c.to_ascii_lowercase()
+        }
+    });
+    dots.chars().chain(rest).collect()
+}
+
+// Errors of a test go into its report as well, so they show up next to it.
+fn print_error(out: &mut String, message: &str, t: &Test) {
+    let _ = writeln!(out, "![{}]({}): ERROR, {message}", t.line, t.name);
+}
+
+fn print_invalid_utf8(out: &mut String, stdout: &[u8], err: std::str::Utf8Error, t: &Test) {
+    let offset = err.valid_up_to();
+    let _ = writeln!(
+        out,
+        "![{}]({}): output is not valid UTF-8, invalid byte at offset {offset}",
+        t.line, t.name
+    );
+    out.push('\n');
+    let _ = writeln!(out, ":got bytes:");
+    out.push_str(&diff::hex_dump_around(stdout, offset));
+}
+
+fn print_timeout(out: &mut String, result: &RunResult, timeout: Duration, t: &Test) {
+    let _ = writeln!(
+        out,
+        "![{}]({}): TIMEOUT, killed after {:?}",
+        t.line, t.name, timeout
+    );
+    out.push('\n');
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let _ = writeln!(out, ":partial output:\n\"{stdout}\"");
+    if !result.stderr.is_empty() {
+        let _ = writeln!(out, ":partial stderr:\n\"{}\"", result.stderr);
+    }
+}
+
+fn print_invocation(out: &mut String, invocation: &Option<String>) {
+    if let Some(invocation) = invocation {
+        let _ = writeln!(out, ":command:\n{invocation}");
+    }
+}
+
+// Formats the command line the way it would be typed in a shell, with the
+// environment variables in front and 'env -i' if the environment is cleared.
+fn format_invocation(argv: &[String], env: &[&(String, String)], clear_env: bool) -> String {
+    let mut words = Vec::new();
+    if clear_env {
+        words.push("env -i".to_string());
+    }
+    for (name, value) in env {
+        words.push(format!("{name}={}", shell_quote(value)));
+    }
+    words.extend(argv.iter().map(|word| shell_quote(word)));
+    words.join(" ")
+}
+
+fn shell_quote(word: &str) -> String {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "_-./:=,+@%".contains(c);
+    if !word.is_empty() && word.chars().all(is_plain) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+const PLACEHOLDERS: [&str; 3] = ["{file}", "{dir}", "{name}"];
+
+fn expand_command(command: &[String], t: &Test, test_file_path: &str) -> Vec<String> {
+    let path = std::path::Path::new(test_file_path);
+    let dir = path.parent().unwrap_or(path).to_string_lossy();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let line = t.line.to_string();
+    // pl-tester never changes its own working directory.
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let cwd = cwd.to_string_lossy();
+
+    let values = [
+        ("{file}", test_file_path),
+        ("{dir}", &dir),
+        ("{name}", &name),
+        ("{line}", &line),
+        ("{cwd}", &cwd),
+    ];
+    let mut argv: Vec<String> = command
+        .iter()
+        .map(|word| expand_placeholders(word, &values))
+        .collect();
+
+    let has_placeholder = command
+        .iter()
+        .any(|word| PLACEHOLDERS.iter().any(|p| word.contains(p)));
+    if !has_placeholder {
+        argv.push(test_file_path.to_string());
+    }
+
+    argv
+}
+
+// Replaces the placeholders in one pass from left to right, so text that was
+// substituted, like a path containing '{line}', is never expanded again.
+fn expand_placeholders(word: &str, values: &[(&str, &str)]) -> String {
+    let mut expanded = String::new();
+    let mut rest = word;
+    while !rest.is_empty() {
+        match values.iter().find(|(p, _)| rest.starts_with(p)) {
+            Some((placeholder, value)) => {
+                expanded.push_str(value);
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                let c = rest.chars().next().unwrap_or_default();
+                expanded.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    expanded
+}
+
+// The expected stdout as bytes, or None if it isn't checked.
+fn expected_stdout(t: &Test) -> Option<&[u8]> {
+    match &t.expected_bytes {
+        Some(bytes) => Some(bytes),
+        None => t.expected.as_ref().map(|expected| expected.as_bytes()),
+    }
+}
+
+fn results_as_expected(out: &mut String, result: &RunResult, t: &Test, escape: bool) -> bool {
+    let status_as_expected = match t.status {
+        ExpectedStatus::Any => true,
+        ExpectedStatus::Nonzero => !result.status.success(),
+        ExpectedStatus::Code(code) => result.status.code() == Some(code),
+    };
+    let expected_stdout = expected_stdout(t);
+    let stdout_as_expected = expected_stdout.is_none_or(|expected| result.stdout == expected);
+    let stderr_as_expected = match &t.expected_stderr {
+        Some(expected) => &result.stderr == expected,
+        None => true,
+    };
+
+    if status_as_expected && stdout_as_expected && stderr_as_expected {
+        return true;
+    }
+
+    if !status_as_expected {
+        let _ = writeln!(
+            out,
+            "![{}]({}): exit status is {}, expected {}",
+            t.line,
+            t.name,
+            format_status(result.status),
+            format_expected_status(&t.status)
+        );
+    }
+    if let Some(expected_stdout) = expected_stdout.filter(|_| !stdout_as_expected) {
+        print_length_difference(out, "output", &result.stdout, expected_stdout, t);
+    }
+    if let Some(expected) = &t.expected_stderr {
+        if !stderr_as_expected {
+            print_length_difference(
+                out,
+                "stderr",
+                result.stderr.as_bytes(),
+                expected.as_bytes(),
+                t,
+            );
+        }
+    }
+
+    out.push('\n');
+    if let Some(expected_stdout) = expected_stdout.filter(|_| !stdout_as_expected) {
+        match (
+            std::str::from_utf8(&result.stdout),
+            std::str::from_utf8(expected_stdout),
+        ) {
+            (Ok(stdout), Ok(expected)) => print_difference(out, "diff", stdout, expected, escape),
+            _ => print_byte_difference(out, &result.stdout, expected_stdout),
+        }
+    }
+    match &t.expected_stderr {
+        Some(expected) if !stderr_as_expected => {
+            print_difference(out, "stderr diff", &result.stderr, expected, escape);
+        }
+        None if !result.stderr.is_empty() => {
+            let _ = writeln!(out, ":stderr:\n\"{}\"", result.stderr);
+        }
+        _ => {}
+    }
+
+    false
+}
+
+fn print_length_difference(out: &mut String, what: &str, result: &[u8], expected: &[u8], t: &Test) {
+    if expected.len() < result.len() {
+        let _ = writeln!(
+            out,
+            "![{}]({}): {what} string length is greater than expected - {} vs {}",
+            t.line,
+            t.name,
+            result.len(),
+            expected.len()
+        );
+    } else if result.len() < expected.len() {
+        let _ = writeln!(
+            out,
+            "![{}]({}): {what} string length is less than expected - {} vs {}",
+            t.line,
+            t.name,
+            result.len(),
+            expected.len()
+        );
+    } else {
+        let _ = writeln!(
+            out,
+            "![{}]({}): {what} differs from expected",
+            t.line, t.name
+        );
+    }
+}
+
+fn format_status(status: std::process::ExitStatus) -> String {
+    match status.code() {
+        Some(code) => code.to_string(),
+        None => format!("'{status}'"),
+    }
+}
+
+fn format_expected_status(status: &ExpectedStatus) -> String {
+    match status {
+        ExpectedStatus::Any => "any".to_string(),
+        ExpectedStatus::Nonzero => "nonzero".to_string(),
+        ExpectedStatus::Code(code) => code.to_string(),
+    }
+}
+
+fn print_difference(out: &mut String, title: &str, result: &str, expected: &str, escape: bool) {
+    let escape = escape || diff::differs_only_in_whitespace(result, expected);
+    let _ = writeln!(out, ":{title}: (-expected +got)");
+    out.push_str(&diff::unified_diff(expected, result, use_color(), escape));
+    if escape {
+        if let Some((offset, line, column)) = diff::first_difference(expected, result) {
+            let _ = writeln!(
+                out,
+                "first difference at byte {offset} (line {line}, column {column})"
+            );
+        }
+    }
+}
+
+fn print_byte_difference(out: &mut String, result: &[u8], expected: &[u8]) {
+    let offset = result
+        .iter()
+        .zip(expected)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let _ = writeln!(out, "first difference at byte {offset}");
+    let _ = writeln!(out, ":got bytes:");
+    out.push_str(&diff::hex_dump_around(result, offset));
+    let _ = writeln!(out, ":expected bytes:");
+    out.push_str(&diff::hex_dump_around(expected, offset));
+}
+
+fn use_color() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+fn read_file(path: &str) -> Option<String> {
+    let file = std::fs::read_to_string(path);
+    if file.is_err() {
+        eprintln!("Error: failed to load test file using path {path}");
+    }
+    file.ok()
+}
+
+// Every parse error of the file is reported. A broken test is skipped up to
+// the next 'TEST' directive, and the file is only run with the tests that
+// parsed correctly when `--run-partial` is given.
+fn parse(path: &str, file: &str, options: &Options) -> Option<TestsData> {
+    let mut p = Parser {
+        path,
+        source: file,
+        line: 1,
+        chars: file.chars(),
+        diagnostics: Vec::new(),
+    };
+
+    let tests_data = parse_suite(&mut p, options);
+    for diagnostic in &p.diagnostics {
+        eprint!("{}", diagnostic::render(path, file, diagnostic));
+    }
+    if 1 < p.diagnostics.len() {
+        eprintln!("Found {} parse errors in '{path}'", p.diagnostics.len());
+    }
+
+    let mut tests_data = tests_data?;
+    tests_data.parse_errors = p.diagnostics.len();
+    if 0 < tests_data.parse_errors && !options.run_partial {
+        return None;
+    }
+    Some(tests_data)
+}
+
+fn parse_suite(p: &mut Parser, options: &Options) -> Option<TestsData> {
+    let mut tests_data = TestsData {
+        path: p.path.to_string(),
+        work_dir: PathBuf::new(),
+        tests: Vec::new(),
+        command: Vec::new(),
+        timeout: options.timeout,
+        args: Vec::new(),
+        env: Vec::new(),
+        clear_env: false,
+        force_clear_env: options.clear_env,
+        escape: options.escape,
+        filtered: 0,
+        parse_errors: 0,
+    };
+
+    let mut command = String::new();
+    let mut command_offset = 0;
+    let mut final_newline = FinalNewline::Keep;
+    loop {
+        skip_whitespaces_and_comments(p)?;
+        let rest = p.chars.as_str();
+        if rest.starts_with("COMMAND:") {
+            command_offset = offset(p) + "COMMAND:".len();
+            command = parse_command(p)?;
+        } else if rest.starts_with("TIMEOUT:") {
+            tests_data.timeout = parse_directive(p, "TIMEOUT:", parse_duration)?;
+        } else if rest.starts_with("FINAL_NEWLINE:") {
+            final_newline = parse_directive(p, "FINAL_NEWLINE:", parse_final_newline)?;
+        } else if rest.starts_with("ARGS:") {
+            let args = parse_directive(p, "ARGS:", split_command)?;
+            tests_data.args.extend(args);
+        } else if rest.starts_with("ENV:") {
+            tests_data
+                .env
+                .push(parse_directive(p, "ENV:", parse_env_var)?);
+        } else if rest.starts_with("CLEAR_ENV:") {
+            tests_data.clear_env = parse_directive(p, "CLEAR_ENV:", parse_bool)?;
+        } else {
+            break;
+        }
+    }
+    // The command line was checked by `parse_args`, only the file's own
+    // command is reported at its location.
+    tests_data.command = match (&options.command, split_command(&command)) {
+        (Some(cli_command), _) => cli_command.clone(),
+        (None, Ok(command)) => command,
+        (None, Err(err)) => {
+            error_at(p, command_offset, &err, None);
+            return None;
+        }
+    };
+    if tests_data.command.is_empty() {
+        error_at(
+            p,
+            0,
+            "no command to run the tests with",
+            Some(
+                "add a 'COMMAND:' directive at the top of the file or pass it on the command line"
+                    .to_string(),
+            ),
+        );
+        return None;
+    }
+
+    loop {
+        if skip_whitespaces_and_comments(p).is_none() || is_at_end(p) {
+            break;
+        }
+        match parse_test(p, final_newline) {
+            Some(test) => tests_data.tests.push(test),
+            None => skip_to_next_test(p),
+        }
+    }
+
+    Some(tests_data)
+}
+
+// Recovers from a broken test by skipping to the next line that starts with
+// a 'TEST' directive, possibly after markers.
+fn skip_to_next_test(p: &mut Parser) {
+    loop {
+        while !is_at_end(p) && peek(p) != '\n' {
+            advance(p);
+        }
+        if is_at_end(p) {
+            return;
+        }
+        advance(p);
+        p.line += 1;
+
+        let mut rest = p.chars.as_str();
+        while let Some(marker) = ["SKIP", "XFAIL", "ONLY"]
+            .into_iter()
+            .find(|m| rest.starts_with(m) && rest[m.len()..].starts_with([' ', '\t']))
+        {
+            rest = rest[marker.len()..].trim_start_matches([' ', '\t']);
+        }
+        if rest.starts_with("TEST") {
+            return;
+        }
+    }
+}
+
+// Byte offset of the parser in the source.
+fn offset(p: &Parser) -> usize {
+    p.source.len() - p.chars.as_str().len()
+}
+
+fn error(p: &mut Parser, message: &str) {
+    error_at(p, offset(p), message, None);
+}
+
+fn error_at(p: &mut Parser, offset: usize, message: &str, hint: Option<String>) {
+    p.diagnostics.push(diagnostic::Diagnostic {
+        offset,
+        message: message.to_string(),
+        hint,
+    });
+}
+
+fn peek(p: &Parser) -> char {
+    p.chars.clone().next().unwrap_or_default()
+}
+
+fn advance(p: &mut Parser) -> char {
+    p.chars.next().unwrap_or_default()
+}
+
+fn skip_whitespaces(p: &mut Parser) {
+    loop {
+        match peek(p) {
+            '\n' => p.line += 1,
+            ' ' | '\t' | '\r' => {}
+            _ => break,
+        }
+        advance(p);
+    }
+}
+
+// Comments are only recognized at the top level of a file, between tests,
+// never inside of their blocks. Line comments start with '#' or '//', block
+// comments are enclosed in '/*' and '*/'.
+fn skip_whitespaces_and_comments(p: &mut Parser) -> Option<()> {
+    loop {
+        skip_whitespaces(p);
+        let rest = p.chars.as_str();
+        if rest.starts_with('#') || rest.starts_with("//") {
+            while !is_at_end(p) && peek(p) != '\n' {
+                advance(p);
+            }
+        } else if rest.starts_with("/*") {
+            let start = offset(p);
+            skip_str(p, "/*")?;
+            while !p.chars.as_str().starts_with("*/") {
+                if is_at_end(p) {
+                    error_at(
+                        p,
+                        start,
+                        "unterminated block comment",
+                        Some(format!(
+                            "comment opened on line {} is never closed with '*/'",
+                            diagnostic::line_of(p.source, start)
+                        )),
+                    );
+                    return None;
+                }
+                if advance(p) == '\n' {
+                    p.line += 1;
+                }
+            }
+            skip_str(p, "*/")?;
+        } else {
+            return Some(());
+        }
+    }
+}
+
+fn is_at_end(p: &mut Parser) -> bool {
+    peek(p) == '\0'
+}
+
+fn parse_command(p: &mut Parser) -> Option<String> {
+    skip_str(p, "COMMAND:")?;
+
+    let start = p.chars.as_str();
+    while !is_at_end(p) && peek(p) != '\n' {
+        advance(p);
+    }
+
+    if is_at_end(p) {
+        error(p, "expected tests after the 'COMMAND:' directive");
+        return None;
+    }
+
+    let command = get_substr(p, start).trim_end().to_string();
+    if command.is_empty() {
+        error(p, "expected a command after the 'COMMAND:' directive");
+        return None;
+    }
+
+    Some(command)
+}
+
+// Splits a command line into words the way a POSIX shell does: whitespace
+// separates words, single quotes keep everything literally, double quotes
+// allow backslash escapes of '"', '\\', '$' and '`', and a backslash outside
+// of quotes escapes the next character.
+fn split_command(command: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\r' | '\n' => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated single quote in command".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => {}
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated double quote in command".to_string()),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+
+    Ok(words)
+}
+
+fn parse_test(p: &mut Parser, final_newline: FinalNewline) -> Option<Test> {
+    // Markers are written before the 'TEST' directive, e.g. 'SKIP TEST name:'.
+    let mut markers = Vec::new();
+    loop {
+        let rest = p.chars.as_str();
+        let Some(marker) = ["SKIP", "XFAIL", "ONLY"]
+            .into_iter()
+            .find(|m| rest.starts_with(m) && rest[m.len()..].starts_with([' ', '\t']))
+        else {
+            break;
+        };
+        skip_str(p, marker)?;
+        markers.push(marker);
+        while peek(p) == ' ' || peek(p) == '\t' {
+            advance(p);
+        }
+    }
+
+    if !p.chars.as_str().starts_with("TEST") {
+        let hint = if markers.is_empty() {
+            "tests start with a line like 'TEST name:', comments start with '#' or '//'"
+        } else {
+            "markers are written in front of 'TEST' on the same line"
+        };
+        error_at(
+            p,
+            offset(p),
+            "expected 'TEST' directive",
+            Some(hint.to_string()),
+        );
+        return None;
+    }
+
+    skip_str(p, "TEST");
+
+    let mut test = Test {
+        name: String::new(),
+        line: p.line,
+        input: String::new(),
+        files: Vec::new(),
+        entry: None,
+        expected: None,
+        expected_bytes: None,
+        expected_format: ExpectedFormat::Text,
+        expected_span: None,
+        expected_stderr: None,
+        stderr_span: None,
+        stdin: None,
+        args: Vec::new(),
+        env: Vec::new(),
+        clear_env: None,
+        status: ExpectedStatus::Any,
+        timeout: None,
+        final_newline,
+        skip: markers.contains(&"SKIP").then(String::new),
+        xfail: markers.contains(&"XFAIL").then(String::new),
+        only: markers.contains(&"ONLY"),
+    };
+
+    test.name = parse_test_name(p)?;
+    skip_whitespaces(p);
+    // Without 'INPUT:' or 'STDOUT:' sections, the test is written as two
+    // blocks, the program and its expected output.
+    if !parse_test_directives(p, &mut test, true)? {
+        let mut chained = None;
+        test.input = parse_block(p, &mut chained)?.0;
+        let (expected, span) = parse_block(p, &mut chained)?;
+        test.expected = Some(expected);
+        test.expected_span = Some(span);
+        skip_whitespaces(p);
+        parse_test_directives(p, &mut test, false)?;
+    }
+
+    if test.final_newline == FinalNewline::Strip {
+        let blocks = [Some(&mut test.input)].into_iter().chain([
+            test.expected.as_mut(),
+            test.stdin.as_mut(),
+            test.expected_stderr.as_mut(),
+        ]);
+        for block in blocks.flatten() {
+            strip_final_newline(block);
+        }
+        for (_, content) in &mut test.files {
+            strip_final_newline(content);
+        }
+    }
+
+    let (Some(expected), Some(span)) = (&test.expected, &test.expected_span) else {
+        return Some(test);
+    };
+    let bytes = match test.expected_format {
+        ExpectedFormat::Text => return Some(test),
+        ExpectedFormat::Hex => parse_hex_bytes(expected),
+        ExpectedFormat::Escaped => parse_escaped_bytes(expected),
+    };
+    match bytes {
+        Ok(bytes) => test.expected_bytes = Some(bytes),
+        Err(err) => {
+            error_at(p, span.range.start, &err, None);
+            return None;
         }
-        println!(
-            "\nSuccessfully completed {} out of {} tests.",
-            tests_data.tests.len() - failed_tests.len(),
-            tests_data.tests.len()
-        );
     }
-    println!();
 
-    Some(())
+    Some(test)
 }
 
-fn run_test(t: &Test, td: &TestsData) -> Option<()> {
-    let test_file_name = t
-        .name
-        .chars()
-        .map(|c| {
-            if c.is_whitespace() {
-                '_'
-            } else {
-                c.to_ascii_lowercase()
-            }
-        })
-        .collect::<String>();
-    let test_file_path = format!("/tmp/pltest/{}", &test_file_name);
-    let cmd_str = &td.command;
-
-    let mut file = match std::fs::File::create(&test_file_path) {
-        Ok(file) => file,
-        Err(err) => {
-            eprintln!(
-                "Error: can't create test file at '{test_file_path}', {:?}",
-                err
+// Directives are written on their own lines, either between the 'TEST' line
+// and the first separator or after the expected output. Block directives like
+// 'STDIN:' and 'STDERR:' are followed by a block with its own separator:
+//
+//     STDERR:
+//     ---
+//     error: unknown variable 'x'
+//     ---
+//
+// The 'INPUT:', 'STDOUT:', 'FILE:' and 'ENTRY:' sections replace the two
+// positional blocks, so they're only allowed with `sections`. Returns whether
+// any of them was found.
+fn parse_test_directives(p: &mut Parser, test: &mut Test, sections: bool) -> Option<bool> {
+    let mut found_section = false;
+    let mut has_input = false;
+    let start = offset(p);
+    loop {
+        let rest = p.chars.as_str();
+        let section = ["INPUT:", "STDOUT:", "FILE:", "ENTRY:"]
+            .into_iter()
+            .find(|s| rest.starts_with(s));
+        if let Some(section) = section.filter(|_| !sections) {
+            let hint =
+                "a test has either two blocks for the program and its output or named sections";
+            error_at(
+                p,
+                offset(p),
+                &format!("unexpected '{section}' section"),
+                Some(hint.to_string()),
             );
             return None;
         }
-    };
-    if let Err(_) = file.write_all(t.input.as_bytes()) {
-        eprintln!("Error: can't write test input to temporary file at '{test_file_path}'");
-    }
 
-    let mut cmd = std::process::Command::new(&cmd_str);
-    cmd.arg(&test_file_path);
-    cmd.stdout(Stdio::piped());
-
-    match cmd.spawn() {
-        Ok(child) => match child.wait_with_output() {
-            Ok(output) => {
-                let result = unsafe { String::from_utf8_unchecked(output.stdout) };
-                if !results_as_expected(&result, t) {
+        if let Some(section @ ("INPUT:" | "STDOUT:")) = section {
+            let (text, span) = parse_directive_block(p, section)?;
+            if section == "INPUT:" {
+                test.input = text;
+                has_input = true;
+            } else {
+                test.expected = Some(text);
+                test.expected_span = Some(span);
+            }
+            found_section = true;
+        } else if let Some(section @ ("FILE:" | "ENTRY:")) = section {
+            let start = offset(p);
+            let path = parse_directive(p, section, parse_file_path)?;
+            let content = parse_block(p, &mut None)?.0;
+            if section == "ENTRY:" {
+                if test.entry.is_some() {
+                    error_at(p, start, "a test can only have one 'ENTRY:' file", None);
                     return None;
                 }
+                test.entry = Some(test.files.len());
             }
-            Err(err) => {
-                eprintln!("{}", err);
-                return None;
-            }
-        },
-        Err(err) => {
-            eprintln!("Error: the '{cmd_str}' failed to run.\nReason: {:?}", err);
-            return None;
+            test.files.push((path, content));
+            found_section = true;
+        } else if rest.starts_with("ARGS:") {
+            test.args
+                .extend(parse_directive(p, "ARGS:", split_command)?);
+        } else if rest.starts_with("ENV:") {
+            test.env.push(parse_directive(p, "ENV:", parse_env_var)?);
+        } else if rest.starts_with("CLEAR_ENV:") {
+            test.clear_env = Some(parse_directive(p, "CLEAR_ENV:", parse_bool)?);
+        } else if rest.starts_with("EXPECTED:") {
+            test.expected_format = parse_directive(p, "EXPECTED:", parse_expected_format)?;
+        } else if rest.starts_with("STATUS:") {
+            test.status = parse_directive(p, "STATUS:", parse_expected_status)?;
+        } else if rest.starts_with("TIMEOUT:") {
+            test.timeout = Some(parse_directive(p, "TIMEOUT:", parse_duration)?);
+        } else if rest.starts_with("FINAL_NEWLINE:") {
+            test.final_newline = parse_directive(p, "FINAL_NEWLINE:", parse_final_newline)?;
+        } else if rest.starts_with("SKIP:") {
+            test.skip = Some(parse_directive_value(p, "SKIP:")?);
+        } else if rest.starts_with("XFAIL:") {
+            test.xfail = Some(parse_directive_value(p, "XFAIL:")?);
+        } else if rest.starts_with("STDERR:") {
+            let (stderr, span) = parse_directive_block(p, "STDERR:")?;
+            test.expected_stderr = Some(stderr);
+            test.stderr_span = Some(span);
+        } else if rest.starts_with("STDIN:") {
+            test.stdin = Some(parse_directive_block(p, "STDIN:")?.0);
+        } else {
+            break;
         }
+        skip_whitespaces(p);
     }
 
-    Some(())
-}
-
-fn results_as_expected(result: &str, t: &Test) -> bool {
-    if result == t.expected {
-        return true;
+    // Without an 'INPUT:' section, the first file is run unless another one
+    // is declared with 'ENTRY:'.
+    if has_input && test.entry.is_some() {
+        error_at(
+            p,
+            start,
+            "a test can't have both an 'INPUT:' section and an 'ENTRY:' file",
+            None,
+        );
+        return None;
     }
+    if !has_input && test.entry.is_none() && !test.files.is_empty() {
+        test.entry = Some(0);
+    }
+    Some(found_section)
+}
 
-    if t.expected.len() < result.len() {
-        println!(
-            "![{}]({}): output string length is greater than expected - {} vs {}",
-            t.line,
-            t.name,
-            result.len(),
-            t.expected.len()
-        );
-    } else if result.len() < t.expected.len() {
-        println!(
-            "![{}]({}): output string length is less than expected - {} vs {}",
-            t.line,
-            t.name,
-            result.len(),
-            t.expected.len()
-        );
+// Parses the value of a directive with `parse`, reporting an invalid value at
+// its position in the file.
+fn parse_directive<T>(
+    p: &mut Parser,
+    directive: &str,
+    parse: fn(&str) -> Result<T, String>,
+) -> Option<T> {
+    let value = parse_directive_value(p, directive)?;
+    match parse(&value) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            let start = p.source[..offset(p)].trim_end().len() - value.len();
+            error_at(p, start, &err, None);
+            None
+        }
     }
+}
 
-    println!();
-    print_difference(result, t);
+fn parse_directive_value(p: &mut Parser, directive: &str) -> Option<String> {
+    skip_str(p, directive)?;
 
-    false
-}
+    let start = p.chars.as_str();
+    while !is_at_end(p) && peek(p) != '\n' {
+        advance(p);
+    }
 
-fn print_difference(result: &str, t: &Test) {
-    println!(":got:\n\"{result}\"");
-    println!(":expected:\n\"{}\"", t.expected);
+    let value = get_substr(p, start).trim_end().to_string();
+    if value.is_empty() {
+        error(
+            p,
+            &format!("expected a value after the '{directive}' directive"),
+        );
+        return None;
+    }
+    Some(value)
 }
 
-fn read_file(path: &str) -> Option<String> {
-    let file = std::fs::read_to_string(path);
-    if file.is_err() {
-        eprintln!("Error: failed to load test file using path {path}");
+fn parse_directive_block(p: &mut Parser, directive: &str) -> Option<(String, Span)> {
+    skip_str(p, directive)?;
+    while peek(p) == ' ' || peek(p) == '\t' || peek(p) == '\r' {
+        advance(p);
     }
-    file.ok()
+    if peek(p) != '\n' {
+        error(
+            p,
+            &format!("expected a block on the line after the '{directive}' directive"),
+        );
+        return None;
+    }
+    parse_block(p, &mut None)
 }
 
-fn parse(file: String) -> Option<TestsData> {
-    let mut p = Parser {
-        line: 1,
-        chars: file.chars(),
+// Parses a block opened by a separator like '---' or by a heredoc-style
+// '<<<NAME', which is closed by a line with just NAME and so may contain any
+// separator. The line closing a separator block also opens the next block of
+// the test, so it's passed on in `chained`. A heredoc block doesn't chain,
+// the next block is opened on its own line again.
+fn parse_block(p: &mut Parser, chained: &mut Option<String>) -> Option<(String, Span)> {
+    let opener = match chained.take() {
+        Some(separator) => separator,
+        None => {
+            skip_whitespaces(p);
+            parse_test_separator(p)?
+        }
     };
 
-    let mut tests_data = TestsData {
-        tests: Vec::new(),
-        command: String::new(),
+    let Some(delimiter) = opener.strip_prefix("<<<") else {
+        let (text, range) = parse_separated_test(p, &opener, &opener)?;
+        *chained = Some(opener.clone());
+        return Some((
+            text,
+            Span {
+                range,
+                separator: opener,
+            },
+        ));
     };
-
-    skip_whitespaces(&mut p);
-    tests_data.command = get_command()?;
-
-    loop {
-        skip_whitespaces(&mut p);
-        if is_at_end(&mut p) {
-            break;
-        }
-        let test = parse_test(&mut p)?;
-        tests_data.tests.push(test);
+    if delimiter.is_empty() {
+        error(p, "expected a delimiter name after '<<<'");
+        return None;
     }
-
-    Some(tests_data)
+    let (text, range) = parse_separated_test(p, &opener, delimiter)?;
+    Some((
+        text,
+        Span {
+            range,
+            separator: delimiter.to_string(),
+        },
+    ))
 }
 
-fn get_command() -> Option<String> {
-    let mut args = std::env::args().skip(1);
-    if let Some(cmd) = args.next() {
-        return Some(cmd);
+// Files of a test are written into its directory, so their paths have to
+// stay inside of it.
+fn parse_file_path(value: &str) -> Result<String, String> {
+    let path = Path::new(value);
+    let leaves_dir = path.components().any(|component| {
+        !matches!(
+            component,
+            std::path::Component::Normal(_) | std::path::Component::CurDir
+        )
+    });
+    if leaves_dir {
+        return Err(format!(
+            "invalid file path '{value}', expected a relative path without '..'"
+        ));
     }
-    return None;
+    Ok(value.to_string())
 }
 
-fn peek(p: &Parser) -> char {
-    p.chars.clone().next().unwrap_or_default()
+// 'ENV:' sets one variable, written as NAME=VALUE.
+fn parse_env_var(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((name, value)) if !name.is_empty() && !name.contains(char::is_whitespace) => {
+            Ok((name.to_string(), value.to_string()))
+        }
+        _ => Err(format!(
+            "invalid 'ENV:' value '{value}', expected NAME=VALUE"
+        )),
+    }
 }
 
-fn advance(p: &mut Parser) -> char {
-    p.chars.next().unwrap_or_default()
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!(
+            "invalid value '{value}', expected 'true' or 'false'"
+        )),
+    }
 }
 
-fn skip_whitespaces(p: &mut Parser) {
-    loop {
-        match peek(p) {
-            '\n' => p.line += 1,
-            ' ' | '\t' | '\r' => {}
-            _ => break,
-        }
-        advance(p);
+fn parse_final_newline(value: &str) -> Result<FinalNewline, String> {
+    match value {
+        "keep" => Ok(FinalNewline::Keep),
+        "strip" => Ok(FinalNewline::Strip),
+        _ => Err(format!(
+            "invalid 'FINAL_NEWLINE:' value '{value}', expected 'keep' or 'strip'"
+        )),
     }
 }
 
-fn is_at_end(p: &mut Parser) -> bool {
-    peek(p) == '\0'
+fn strip_final_newline(block: &mut String) {
+    if block.ends_with('\n') {
+        block.pop();
+        if block.ends_with('\r') {
+            block.pop();
+        }
+    }
 }
 
-fn _parse_command(p: &mut Parser) -> Option<String> {
-    if !p.chars.as_str().starts_with("COMMAND:") {
-        eprintln!("Error: expected 'COMMAND:' directive at top of the file");
-        return None;
+fn parse_expected_format(value: &str) -> Result<ExpectedFormat, String> {
+    match value {
+        "text" => Ok(ExpectedFormat::Text),
+        "hex" => Ok(ExpectedFormat::Hex),
+        "escaped" => Ok(ExpectedFormat::Escaped),
+        _ => Err(format!(
+            "invalid 'EXPECTED:' value '{value}', expected 'text', 'hex' or 'escaped'"
+        )),
     }
+}
 
-    skip_str(p, "COMMAND:")?;
-
-    let start = p.chars.as_str();
-    while !is_at_end(p) && peek(p) != '\n' {
-        advance(p);
+// 'EXPECTED: hex' output is written as pairs of hex digits, whitespace
+// between them is ignored, e.g. "ff fe 41 0a".
+fn parse_hex_bytes(text: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err("odd number of hex digits in expected output".to_string());
     }
 
-    if is_at_end(p) {
-        eprintln!("Expected tests after the 'COMMAND:' directive");
-        return None;
+    let mut bytes = Vec::new();
+    for pair in digits.chunks(2) {
+        let pair: String = pair.iter().collect();
+        // `from_str_radix` would also take a sign like in '+1'.
+        match u8::from_str_radix(&pair, 16) {
+            Ok(byte) if pair.chars().all(|c| c.is_ascii_hexdigit()) => bytes.push(byte),
+            _ => return Err(format!("invalid hex byte '{pair}' in expected output")),
+        }
     }
-
-    let command = get_substring(p, start);
-
-    Some(command)
+    Ok(bytes)
 }
 
-fn parse_test(p: &mut Parser) -> Option<Test> {
-    if !p.chars.as_str().starts_with("TEST") {
-        eprintln!("Error: expected 'TEST' directive");
-        return None;
+// 'EXPECTED: escaped' output is taken literally, except for the escapes
+// '\xNN', '\n', '\r', '\t', '\0' and '\\'. Line breaks in the block are
+// ignored, so every newline of the output has to be written as '\n'.
+fn parse_escaped_bytes(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\n' || c == '\r' {
+            continue;
+        }
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('0') => bytes.push(0),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+                        bytes.push(byte)
+                    }
+                    _ => return Err(format!("invalid escape '\\x{hex}' in expected output")),
+                }
+            }
+            Some(c) => return Err(format!("unknown escape '\\{c}' in expected output")),
+            None => return Err("unfinished escape at the end of expected output".to_string()),
+        }
     }
+    Ok(bytes)
+}
 
-    skip_str(p, "TEST");
-
-    let mut test = Test {
-        name: String::new(),
-        line: p.line,
-        input: String::new(),
-        expected: String::new(),
+// Accepts plain seconds ("5", "2.5") or a number with an "s" or "ms" suffix.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, scale) = if let Some(ms) = value.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(s) = value.strip_suffix('s') {
+        (s, 1.0)
+    } else {
+        (value, 1.0)
     };
 
-    test.name = parse_test_name(p)?;
-    skip_whitespaces(p);
-    let separator = parse_test_separator(p)?;
-    test.input = parse_separated_test(p, &separator)?;
-    test.expected = parse_separated_test(p, &separator)?;
+    // Negative, infinite and too large durations aren't representable.
+    let duration = number.trim().parse::<f64>().ok();
+    match duration.and_then(|n| Duration::try_from_secs_f64(n * scale).ok()) {
+        Some(duration) => Ok(duration),
+        None => Err(format!(
+            "invalid duration '{value}', expected e.g. 5, 2.5s or 500ms"
+        )),
+    }
+}
 
-    Some(test)
+fn parse_expected_status(value: &str) -> Result<ExpectedStatus, String> {
+    match value {
+        "any" => Ok(ExpectedStatus::Any),
+        "nonzero" => Ok(ExpectedStatus::Nonzero),
+        _ => match value.parse() {
+            Ok(code) => Ok(ExpectedStatus::Code(code)),
+            Err(_) => Err(format!(
+                "invalid 'STATUS:' value '{value}', expected a number, 'nonzero' or 'any'"
+            )),
+        },
+    }
 }
 
 fn parse_test_name(p: &mut Parser) -> Option<String> {
@@ -286,12 +2269,12 @@ fn parse_test_name(p: &mut Parser) -> Option<String> {
         advance(p);
     }
 
-    if is_at_end(p) {
-        eprintln!("Error: test name should be on the same line with the 'TEST' directive");
+    if is_at_end(p) || peek(p) == '\n' {
+        error(p, "expected ':' after the test name");
         return None;
     }
-    if peek(p) == '\n' {
-        eprintln!("Error: expected test name after the 'TEST' directive");
+    if get_substr(p, start).is_empty() {
+        error(p, "expected test name after the 'TEST' directive");
         return None;
     }
 
@@ -302,10 +2285,20 @@ fn parse_test_name(p: &mut Parser) -> Option<String> {
 
 fn parse_test_separator(p: &mut Parser) -> Option<String> {
     let start = p.chars.as_str();
-    while !is_whitespace(peek(p)) {
+    while !is_at_end(p) && !is_whitespace(peek(p)) {
         advance(p);
     }
-    Some(get_substring(p, start))
+    let separator = get_substring(p, start);
+    if separator.is_empty() {
+        error_at(
+            p,
+            offset(p),
+            "expected a separator line",
+            Some("blocks of a test are enclosed in separator lines like '---'".to_string()),
+        );
+        return None;
+    }
+    Some(separator)
 }
 
 fn is_whitespace(c: char) -> bool {
@@ -320,30 +2313,58 @@ fn get_substr<'a>(p: &Parser, start: &'a str) -> &'a str {
     start[0..len].trim_start()
 }
 
-fn parse_separated_test(p: &mut Parser, separator: &str) -> Option<String> {
-    let first_char = separator.chars().next().unwrap_or_default();
-    let start = p.chars.as_str();
-
-    while !is_at_end(p) {
-        if peek(p) == '\n' {
-            p.line += 1;
-        }
-
-        if peek(p) == first_char {
-            let substr = get_substr(p, start);
+// A block starts on the line after its opener and ends before the next line
+// that is the `separator` alone, trailing whitespace aside. Returns the content
+// of the block, kept exactly as written, and its byte range in the source. The
+// parser is left right after the closing separator.
+fn parse_separated_test(
+    p: &mut Parser,
+    opener: &str,
+    separator: &str,
+) -> Option<(String, Range<usize>)> {
+    let opened_at = offset(p) - opener.len();
+    while matches!(peek(p), ' ' | '\t' | '\r') {
+        advance(p);
+    }
+    if !is_at_end(p) && peek(p) != '\n' {
+        error_at(
+            p,
+            offset(p),
+            &format!("unexpected text after '{opener}'"),
+            Some("a separator has to be alone on its line".to_string()),
+        );
+        return None;
+    }
 
+    if !is_at_end(p) {
+        advance(p);
+        p.line += 1;
+        let start = offset(p);
+        loop {
             let rest = p.chars.as_str();
-            if &rest[0..separator.len()] == separator {
-                skip_str(p, separator);
+            let line = rest.split('\n').next().unwrap_or_default();
+            if line.trim_end_matches([' ', '\t', '\r']) == separator {
+                let end = offset(p);
+                p.chars = rest[separator.len()..].chars();
+                return Some((p.source[start..end].to_string(), start..end));
             }
-            if peek(p) == '\n' {
-                return Some(substr.to_string());
+            if line.len() == rest.len() {
+                break;
             }
+            p.chars = rest[line.len() + 1..].chars();
+            p.line += 1;
         }
-
-        advance(p);
     }
 
+    let line = diagnostic::line_of(p.source, opened_at);
+    let hint = if opener == separator {
+        format!("separator '{separator}' opened on line {line} was never closed")
+    } else {
+        format!(
+            "'{opener}' opened on line {line} was never closed by a line with just '{separator}'"
+        )
+    };
+    error_at(p, opened_at, "unterminated block", Some(hint));
     None
 }
 
@@ -353,3 +2374,441 @@ fn skip_str(p: &mut Parser, str: &str) -> Option<()> {
     }
     Some(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        bless, bless_edits, diagnostic, parse, parse_duration, parse_escaped_bytes,
+        parse_hex_bytes, parse_suite, resolve_program, split_command, test_file_name, Duration,
+        Options, Parser, RunResult, Test, TestsData,
+    };
+
+    fn parse_tests(source: &str) -> Option<Vec<Test>> {
+        let options = Options::default();
+        parse("test.plt", source, &options).map(|tests_data| tests_data.tests)
+    }
+
+    fn parse_test(source: &str) -> Test {
+        let mut tests = parse_tests(&format!("COMMAND: cat\n\n{source}")).unwrap();
+        assert_eq!(tests.len(), 1);
+        tests.remove(0)
+    }
+
+    struct RenderedDiagnostic {
+        message: String,
+        location: String,
+        hint: Option<String>,
+    }
+
+    // Parses like `parse`, but returns the diagnostics instead of printing
+    // them, with the rendered 'file:line:col' of each.
+    fn parse_with_diagnostics(source: &str) -> (Option<TestsData>, Vec<RenderedDiagnostic>) {
+        let mut p = Parser {
+            path: "test.plt",
+            source,
+            line: 1,
+            chars: source.chars(),
+            diagnostics: Vec::new(),
+        };
+        let tests_data = parse_suite(&mut p, &Options::default());
+        let diagnostics = p
+            .diagnostics
+            .iter()
+            .map(|d| {
+                let rendered = diagnostic::render("test.plt", source, d);
+                let location = rendered.lines().nth(1).unwrap();
+                RenderedDiagnostic {
+                    message: d.message.clone(),
+                    location: location.trim_start().trim_start_matches("--> ").to_string(),
+                    hint: d.hint.clone(),
+                }
+            })
+            .collect();
+        (tests_data, diagnostics)
+    }
+
+    fn assert_blocks(t: &Test, input: &str, expected: &str) {
+        assert_eq!(t.input, input);
+        assert_eq!(t.expected.as_deref(), Some(expected));
+    }
+
+    #[test]
+    fn two_blocks() {
+        let t = parse_test("TEST a:\n---\nprint 1\n---\n1\n---\n");
+        assert_eq!(t.name, "a");
+        assert_blocks(&t, "print 1\n", "1\n");
+    }
+
+    #[test]
+    fn separator_with_trailing_whitespace() {
+        let t = parse_test("TEST a:\n--- \t\nprint 1\n---  \n1\n---\t\n");
+        assert_blocks(&t, "print 1\n", "1\n");
+    }
+
+    #[test]
+    fn crlf_line_breaks_are_kept() {
+        let t = parse_test("TEST a:\r\n---\r\nprint 1\r\n---\r\n1\r\n---\r\n");
+        assert_blocks(&t, "print 1\r\n", "1\r\n");
+    }
+
+    #[test]
+    fn empty_blocks() {
+        let t = parse_test("TEST a:\n---\n---\n---\n");
+        assert_blocks(&t, "", "");
+    }
+
+    #[test]
+    fn separator_at_end_of_file() {
+        let t = parse_test("TEST a:\n---\nprint 1\n---\n1\n---");
+        assert_blocks(&t, "print 1\n", "1\n");
+    }
+
+    #[test]
+    fn indented_separator_is_content() {
+        let t = parse_test("TEST a:\n---\nx\n  ---\n\ty\n---\n  ---\n---\n");
+        assert_blocks(&t, "x\n  ---\n\ty\n", "  ---\n");
+    }
+
+    #[test]
+    fn unterminated_block() {
+        assert!(parse_tests("COMMAND: cat\n\nTEST a:\n---\nprint 1\n---\n1\n").is_none());
+    }
+
+    #[test]
+    fn final_newline() {
+        let t = parse_test("TEST a:\nFINAL_NEWLINE: keep\n---\nx\n---\ny\n---\n");
+        assert_blocks(&t, "x\n", "y\n");
+
+        let t = parse_test("TEST a:\nFINAL_NEWLINE: strip\n---\nx\n\n---\ny\r\n---\n");
+        assert_blocks(&t, "x\n", "y");
+
+        let tests = parse_tests(
+            "COMMAND: cat\nFINAL_NEWLINE: strip\n\nTEST a:\n---\nx\n---\ny\n---\n\n\
+             TEST b:\nFINAL_NEWLINE: keep\n---\nx\n---\ny\n---\n",
+        )
+        .unwrap();
+        assert_blocks(&tests[0], "x", "y");
+        assert_blocks(&tests[1], "x\n", "y\n");
+    }
+
+    #[test]
+    fn split_command_words() {
+        assert_eq!(
+            split_command("python3  -u\tmain.py").unwrap(),
+            ["python3", "-u", "main.py"]
+        );
+        assert_eq!(
+            split_command("a 'b c' \"d e\"").unwrap(),
+            ["a", "b c", "d e"]
+        );
+        assert_eq!(split_command("a\\ b 'x'\"y\"z").unwrap(), ["a b", "xyz"]);
+        assert_eq!(split_command("'' \"\"").unwrap(), ["", ""]);
+        assert_eq!(
+            split_command(r#""\"\\\$\n" '\n'"#).unwrap(),
+            [r#""\$\n"#, r"\n"]
+        );
+        assert!(split_command("").unwrap().is_empty());
+        assert!(split_command("a 'b").is_err());
+        assert!(split_command("a \"b").is_err());
+    }
+
+    #[test]
+    fn expected_bytes() {
+        let t = parse_test("TEST a:\nEXPECTED: hex\n---\nx\n---\nff 0a\n41\n---\n");
+        assert_eq!(t.expected_bytes.as_deref(), Some(&[0xff, 0x0a, 0x41][..]));
+
+        let t = parse_test("TEST a:\nEXPECTED: escaped\n---\nx\n---\na\\tb\n\\x00\\n\n---\n");
+        assert_eq!(t.expected_bytes.as_deref(), Some(&b"a\tb\0\n"[..]));
+    }
+
+    #[test]
+    fn hex_bytes() {
+        assert_eq!(
+            parse_hex_bytes("ff FE\n41 0a").unwrap(),
+            [0xff, 0xfe, 0x41, 0x0a]
+        );
+        assert_eq!(parse_hex_bytes("").unwrap(), []);
+        assert!(parse_hex_bytes("f").is_err());
+        assert!(parse_hex_bytes("fg").is_err());
+        assert!(parse_hex_bytes("+1").is_err());
+    }
+
+    #[test]
+    fn escaped_bytes() {
+        assert_eq!(parse_escaped_bytes("a\\n\nb").unwrap(), b"a\nb");
+        assert_eq!(parse_escaped_bytes("\\r\\t\\0\\\\").unwrap(), b"\r\t\0\\");
+        assert_eq!(parse_escaped_bytes("\\xfF\\x00").unwrap(), [0xff, 0x00]);
+        assert_eq!(parse_escaped_bytes("é").unwrap(), "é".as_bytes());
+        assert!(parse_escaped_bytes("\\x4").is_err());
+        assert!(parse_escaped_bytes("\\xzz").is_err());
+        assert!(parse_escaped_bytes("\\x+1").is_err());
+        assert!(parse_escaped_bytes("\\q").is_err());
+        assert!(parse_escaped_bytes("a\\").is_err());
+    }
+
+    #[test]
+    fn heredoc_blocks() {
+        let t = parse_test("TEST a:\n<<<END\n---\nx\n---\nEND\n<<<OUT\ny\nOUT\n");
+        assert_blocks(&t, "---\nx\n---\n", "y\n");
+
+        // A heredoc doesn't chain, so a separator block can follow it.
+        let t = parse_test("TEST a:\n<<<END\nx\nEND\n---\ny\n---\n");
+        assert_blocks(&t, "x\n", "y\n");
+
+        // The delimiter has to be alone on its line, and may be followed by
+        // trailing whitespace.
+        let t = parse_test("TEST a:\n<<<END\n END\nEND \n<<<END\ny\nEND");
+        assert_blocks(&t, " END\n", "y\n");
+
+        assert!(parse_tests("COMMAND: cat\n\nTEST a:\n<<<\nx\n\n---\ny\n---\n").is_none());
+        assert!(parse_tests("COMMAND: cat\n\nTEST a:\n<<<END\nx\n---\ny\n---\n").is_none());
+    }
+
+    #[test]
+    fn named_sections() {
+        let t = parse_test(
+            "TEST a:\nSTDOUT:\n---\n1\n---\nARGS: -v 'two words'\nENV: LANG=C\n\
+             INPUT:\n<<<END\nprint 1\nEND\nSTDIN:\n---\nin\n---\n",
+        );
+        assert_blocks(&t, "print 1\n", "1\n");
+        assert_eq!(t.args, ["-v", "two words"]);
+        assert_eq!(t.env, [("LANG".to_string(), "C".to_string())]);
+        assert_eq!(t.stdin.as_deref(), Some("in\n"));
+        assert!(t.files.is_empty());
+        assert_eq!(t.entry, None);
+
+        // Output without a section isn't checked.
+        let t = parse_test("TEST a:\nINPUT:\n---\nx\n---\n");
+        assert_eq!(t.input, "x\n");
+        assert_eq!(t.expected, None);
+    }
+
+    #[test]
+    fn sections_after_blocks_are_rejected() {
+        let source = "COMMAND: cat\n\nTEST a:\n---\nx\n---\ny\n---\nSTDOUT:\n---\ny\n---\n";
+        assert!(parse_tests(source).is_none());
+    }
+
+    #[test]
+    fn file_sections() {
+        let t = parse_test(
+            "TEST a:\nFILE: lib/util.ml\n---\nlet x = 1\n---\nENTRY: main.ml\n---\n\
+             import \"util.ml\"\n---\nSTDOUT:\n---\n1\n---\n",
+        );
+        let files = [
+            ("lib/util.ml".to_string(), "let x = 1\n".to_string()),
+            ("main.ml".to_string(), "import \"util.ml\"\n".to_string()),
+        ];
+        assert_eq!(t.files, files);
+        assert_eq!(t.entry, Some(1));
+        assert_eq!(t.expected.as_deref(), Some("1\n"));
+
+        let t = parse_test("TEST a:\nFILE: main.ml\n---\nx\n---\n");
+        assert_eq!(t.entry, Some(0));
+
+        let source = "COMMAND: cat\n\nTEST a:\nFILE: ../main.ml\n---\nx\n---\n";
+        assert!(parse_tests(source).is_none());
+        let source = "COMMAND: cat\n\nTEST a:\nINPUT:\n---\nx\n---\nENTRY: m\n---\nx\n---\n";
+        assert!(parse_tests(source).is_none());
+    }
+
+    #[test]
+    fn resolve_program_only_resolves_paths() {
+        let cwd = std::env::current_dir().unwrap();
+        let mut command = ["./interp".to_string(), "lib/x.py".to_string()];
+        resolve_program(&mut command);
+        assert_eq!(command[0], cwd.join("interp").to_string_lossy());
+        assert_eq!(command[1], "lib/x.py");
+
+        for program in ["python3", "{cwd}/interp", "./{name}"] {
+            let mut command = [program.to_string()];
+            resolve_program(&mut command);
+            assert_eq!(command[0], program);
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("5"), Ok(Duration::from_secs(5)));
+        assert_eq!(parse_duration("2.5s"), Ok(Duration::from_millis(2500)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("0"), Ok(Duration::ZERO));
+        for value in ["", "s", "-1", "1e30", "1e300ms", "inf", "NaN", "5m"] {
+            assert!(parse_duration(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn test_file_names() {
+        assert_eq!(test_file_name("Hello World.py"), "hello_world.py");
+        assert_eq!(test_file_name("lexer/strings"), "lexer_strings");
+        assert_eq!(test_file_name("a\\b"), "a_b");
+        assert_eq!(test_file_name(".."), "__");
+        assert_eq!(test_file_name("../../x"), "___.._x");
+        assert_eq!(test_file_name(".hidden.rc"), "_hidden.rc");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bless_with_final_newline_strip() {
+        let source = "COMMAND: cat\nFINAL_NEWLINE: strip\n\n\
+                      TEST a:\n---\nx\n---\nold\n---\n\n\
+                      TEST b:\n---\ny\n---\n---\n";
+        let outputs = ["new", "1\n2"];
+        let mut edits = Vec::new();
+        for (t, stdout) in parse_tests(source).unwrap().iter().zip(outputs) {
+            let result = RunResult {
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: String::new(),
+                status: std::os::unix::process::ExitStatusExt::from_raw(0),
+            };
+            edits.extend(bless_edits(t, &result));
+        }
+        let blessed = bless::apply(source, edits);
+        assert_eq!(
+            blessed,
+            "COMMAND: cat\nFINAL_NEWLINE: strip\n\n\
+             TEST a:\n---\nx\n---\nnew\n---\n\n\
+             TEST b:\n---\ny\n---\n1\n2\n---\n"
+        );
+        let tests = parse_tests(&blessed).unwrap();
+        assert_eq!(tests[0].expected.as_deref(), Some("new"));
+        assert_eq!(tests[1].expected.as_deref(), Some("1\n2"));
+    }
+
+    #[test]
+    fn recovery_after_parse_errors() {
+        let source = "\
+COMMAND: cat
+
+TEST good 1:
+---
+a
+---
+a
+---
+
+TETS typo:
+---
+x
+---
+x
+---
+
+TEST bad timeout:
+TIMEOUT: soon
+---
+x
+---
+x
+---
+
+SKIP TEST good 2:
+---
+b
+---
+b
+---
+
+TEST no colon
+---
+TEST good 3:
+---
+c
+---
+c
+---
+
+TEST unterminated:
+---
+x
+";
+        let (tests_data, diagnostics) = parse_with_diagnostics(source);
+        let tests_data = tests_data.unwrap();
+        let locations: Vec<&str> = diagnostics.iter().map(|d| d.location.as_str()).collect();
+        assert_eq!(
+            locations,
+            [
+                "test.plt:10:1",
+                "test.plt:18:10",
+                "test.plt:32:14",
+                "test.plt:42:1"
+            ]
+        );
+
+        let names: Vec<&str> = tests_data.tests.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["good 1", "good 2", "good 3"]);
+        assert_blocks(&tests_data.tests[2], "c\n", "c\n");
+        assert_eq!(tests_data.tests[2].line, 34);
+
+        // Without --run-partial, a file with errors isn't run at all.
+        assert!(parse_tests(source).is_none());
+        let options = Options {
+            run_partial: true,
+            ..Options::default()
+        };
+        let tests_data = parse("test.plt", source, &options).unwrap();
+        assert_eq!(tests_data.tests.len(), 3);
+        assert_eq!(tests_data.parse_errors, 4);
+    }
+
+    #[test]
+    fn comments_between_tests() {
+        let source = "\
+# top
+// also top
+COMMAND: cat
+/* a block
+   comment */
+TEST a:
+---
+x
+---
+x
+---
+  # between /* not a block */
+/*
+TEST commented out:
+---
+*/ // trailing
+TEST b:
+---
+y
+---
+y
+---
+";
+        let tests = parse_tests(source).unwrap();
+        let names: Vec<&str> = tests.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(tests[0].line, 6);
+        assert_eq!(tests[1].line, 17);
+    }
+
+    #[test]
+    fn no_comments_inside_blocks() {
+        let t = parse_test(
+            "TEST a:\n---\n# one\n// two\n/* three\n---\n  # out */\n//\n---\n\
+             STDIN:\n<<<END\n/*\n#\nEND\n",
+        );
+        assert_blocks(&t, "# one\n// two\n/* three\n", "  # out */\n//\n");
+        assert_eq!(t.stdin.as_deref(), Some("/*\n#\n"));
+
+        let t = parse_test("TEST a:\nINPUT:\n---\n/*\n---\nSTDOUT:\n---\n*/\n---\n");
+        assert_blocks(&t, "/*\n", "*/\n");
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let source = "COMMAND: cat\n\nTEST a:\n---\nx\n---\nx\n---\n\n  /* TEST b:\n---\n";
+        let (tests_data, diagnostics) = parse_with_diagnostics(source);
+        assert_eq!(tests_data.unwrap().tests.len(), 1);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "unterminated block comment");
+        assert_eq!(diagnostics[0].location, "test.plt:10:3");
+        assert_eq!(
+            diagnostics[0].hint.as_deref(),
+            Some("comment opened on line 10 is never closed with '*/'")
+        );
+    }
+}